@@ -0,0 +1,213 @@
+//! Anki "Notes in Plain Text" exports.
+//!
+//! Anki 2.1.55 and later start the file with `#key:value` header lines giving
+//! the separator, whether fields contain HTML, and which columns hold tags,
+//! the deck or other note metadata. The remaining columns are note fields;
+//! the first two become the card's front and back. Fields are quoted CSV
+//! style when they contain the separator, quotes or line breaks.
+
+use crate::csv::{Row, read_rows};
+use crate::deck::content_id;
+use crate::import::{CardCollector, ImportResult, SkipReason};
+
+struct Headers {
+    separator: char,
+    html: bool,
+    deck: Option<String>,
+    /// Zero-based column holding space-separated tags.
+    tags_column: Option<usize>,
+    /// Zero-based column holding Anki's note guid.
+    guid_column: Option<usize>,
+    /// Zero-based columns that hold note metadata rather than fields.
+    metadata_columns: Vec<usize>,
+}
+
+/// True if the text starts with the header lines Anki always writes.
+pub fn is_anki_export(data: &str) -> bool {
+    header_lines(data).any(|(key, _)| key == "separator" || key == "html")
+}
+
+/// `#key:value` lines at the top of the file.
+fn header_lines(data: &str) -> impl Iterator<Item = (&str, &str)> {
+    data.lines()
+        .map_while(|line| line.strip_prefix('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key, value.trim()))
+}
+
+fn read_headers(data: &str) -> Headers {
+    let mut headers = Headers {
+        separator: '\t',
+        html: false,
+        deck: None,
+        tags_column: None,
+        guid_column: None,
+        metadata_columns: Vec::new(),
+    };
+    // Anki numbers columns from 1
+    let column = |value: &str| value.parse::<usize>().ok().filter(|&c| c > 0).map(|c| c - 1);
+    for (key, value) in header_lines(data) {
+        match key {
+            "separator" => {
+                headers.separator = match value {
+                    "tab" => '\t',
+                    "comma" => ',',
+                    "semicolon" => ';',
+                    "space" => ' ',
+                    "pipe" => '|',
+                    "colon" => ':',
+                    other => other.chars().next().unwrap_or('\t'),
+                }
+            }
+            "html" => headers.html = value == "true",
+            "deck" => headers.deck = Some(value.to_string()),
+            "tags column" => {
+                headers.tags_column = column(value);
+                headers.metadata_columns.extend(column(value));
+            }
+            "guid column" => {
+                headers.guid_column = column(value);
+                headers.metadata_columns.extend(column(value));
+            }
+            "notetype column" | "deck column" => headers.metadata_columns.extend(column(value)),
+            _ => {}
+        }
+    }
+    headers
+}
+
+/// Parse an Anki plain-text export. The `#deck:` header, if any, names the deck.
+/// Cards from a file with a guid column get ids derived from the guid, so a
+/// re-exported note matches its card even if the front was edited.
+pub fn parse_anki(data: &str) -> ImportResult {
+    let headers = read_headers(data);
+    let mut collector = CardCollector::new();
+    let (rows, unterminated) = read_rows(data, headers.separator);
+
+    for (line, row) in rows {
+        let columns = match row {
+            Row::Comment(_) => continue,
+            Row::Fields(columns) => columns,
+        };
+        if columns.iter().all(|c| c.trim().is_empty()) {
+            continue;
+        }
+        let tags = headers
+            .tags_column
+            .and_then(|c| columns.get(c))
+            .map(|t| t.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+        let id = headers
+            .guid_column
+            .and_then(|c| columns.get(c))
+            .filter(|g| !g.trim().is_empty())
+            .map_or(0, |g| content_id(g.trim(), 0));
+        let fields: Vec<String> = columns
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !headers.metadata_columns.contains(i))
+            .map(|(_, f)| if headers.html { html_to_text(&f) } else { f })
+            .collect();
+        if fields.len() < 2 {
+            collector.skip(line, SkipReason::MissingField);
+            continue;
+        }
+        collector.add(line, fields[0].trim(), fields[1].trim(), id, tags);
+    }
+    if let Some(line) = unterminated {
+        collector.skip(line, SkipReason::UnterminatedQuote);
+    }
+    collector.finish(headers.deck)
+}
+
+/// Reduce Anki's field HTML to plain text: line-break and block tags become
+/// newlines, other tags are dropped, and entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let tag = rest[1..end].trim().to_ascii_lowercase();
+                let name = tag.split(|c: char| c.is_whitespace() || c == '/').find(|s| !s.is_empty()).unwrap_or("");
+                let closing = tag.starts_with('/');
+                match name {
+                    "br" => out.push('\n'),
+                    "div" | "p" | "li" | "tr" if !closing && !out.is_empty() && !out.ends_with('\n') => out.push('\n'),
+                    _ => {}
+                }
+                rest = &rest[end + 1..];
+                continue;
+            }
+        } else if c == '&' {
+            if let Some((decoded, len)) = decode_entity(rest) {
+                out.push(decoded);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Decode an entity at the start of `text`, returning the character and the
+/// entity's length in bytes.
+fn decode_entity(text: &str) -> Option<(char, usize)> {
+    let end = text.get(..12).unwrap_or(text).find(';')?;
+    let entity = &text[1..end];
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let value = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(value)?
+        }
+    };
+    Some((c, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anki_exports_parse() {
+        let data = "#separator:tab\n#html:true\n#deck:Spanish\n#tags column:3\n\
+                    hola\thello<br>hi &amp; <b>hey</b>\tgreeting basic\n\
+                    \"a\tb\"\tc&#233;\t\n";
+        assert!(is_anki_export(data));
+        let result = parse_anki(data);
+        assert_eq!(result.name.as_deref(), Some("Spanish"));
+        assert_eq!(result.report.issues, vec![]);
+        assert_eq!(result.cards[0].back, "hello\nhi & hey");
+        assert_eq!(result.cards[0].tags, vec!["greeting", "basic"]);
+        assert_eq!(result.cards[1].front, "a\tb");
+        assert_eq!(result.cards[1].back, "cé");
+        assert!(result.cards[1].tags.is_empty());
+    }
+
+    #[test]
+    fn anki_guids_give_stable_ids() {
+        let header = "#separator:tab\n#html:true\n#guid column:1\n";
+        let before = parse_anki(&format!("{}Ab1$xy\thola\thello\n", header));
+        let after = parse_anki(&format!("{}Ab1$xy\t¡hola!\thello\n", header));
+        assert_eq!(before.cards[0].front, "hola");
+        assert_eq!(before.cards[0].id, after.cards[0].id);
+    }
+
+    #[test]
+    fn html_reduces_to_text() {
+        assert_eq!(html_to_text("<div>one</div><div>two<br/>three</div>"), "one\ntwo\nthree");
+        assert_eq!(html_to_text("&lt;b&gt; &#x41;&#66; &bogus; 5 < 6"), "<b> AB &bogus; 5 < 6");
+    }
+}
@@ -0,0 +1,177 @@
+//! Passphrase-encrypted export files, so a deck that leaves the PDDB stays
+//! protected on the computer it lands on.
+//!
+//! The key is derived from the passphrase with PBKDF2-HMAC-SHA256, and the
+//! payload is sealed with ChaCha20-Poly1305, which is quick in software on a
+//! CPU without AES instructions. The header is authenticated along with the
+//! payload, so its KDF parameters can't be altered unnoticed. Any change to
+//! the file, or a wrong passphrase, fails to open rather than yielding
+//! garbage. `tools/fccrypt.py` reads and writes the same format.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+
+use crate::error::Error;
+
+const MAGIC: [u8; 4] = *b"FCEN";
+const VERSION: u8 = 1;
+const KDF_PBKDF2_SHA256: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// [4: magic "FCEN"][u8: version][u8: kdf][u16: reserved][u32: iterations][16: salt][12: nonce]
+const HEADER_LEN: usize = 12 + SALT_LEN + NONCE_LEN;
+/// Bytes sealing adds to a payload.
+pub const SEAL_OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+/// PBKDF2 rounds for new files. The device has no SHA-256 hardware, so this
+/// trades some brute-force cost for a key derivation of a few seconds.
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+/// Files asking for more rounds are refused, so a crafted header can't stall the device.
+const MAX_ITERATIONS: u32 = 2_000_000;
+/// Shortest passphrase accepted when encrypting.
+pub const MIN_PASSPHRASE_CHARS: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    UnsupportedVersion(u8),
+    UnsupportedKdf(u8),
+    /// The header is cut short or asks for an unreasonable number of rounds.
+    Corrupt,
+    /// Authentication failed: a wrong passphrase, or a damaged file.
+    WrongPassphrase,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::UnsupportedVersion(v) => write!(f, "encrypted file version {} is newer than this app", v),
+            CryptoError::UnsupportedKdf(k) => write!(f, "unknown key derivation {}", k),
+            CryptoError::Corrupt => write!(f, "encrypted file is truncated or malformed"),
+            CryptoError::WrongPassphrase => write!(f, "wrong passphrase or damaged file"),
+        }
+    }
+}
+
+/// True if the data starts like an encrypted export.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// A passphrase with a fresh salt and nonce from the TRNG, ready to seal
+/// one export.
+pub struct Sealer {
+    passphrase: String,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+}
+
+impl Sealer {
+    /// Fails if the TRNG can't be read; there is no weaker fallback.
+    pub fn new(passphrase: &str, trng: &trng::Trng) -> Result<Self, Error> {
+        let mut random = [0u8; SALT_LEN + NONCE_LEN];
+        for chunk in random.chunks_mut(4) {
+            let word = trng.get_u32().map_err(|_| Error::Random)?;
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        salt.copy_from_slice(&random[..SALT_LEN]);
+        nonce.copy_from_slice(&random[SALT_LEN..]);
+        Ok(Self { passphrase: passphrase.to_string(), salt, nonce })
+    }
+
+    /// Encrypt with `DEFAULT_ITERATIONS` rounds. Consumes the sealer, so its
+    /// nonce is used once.
+    pub fn seal(self, plain: &[u8]) -> Vec<u8> {
+        seal_with(plain, &self.passphrase, DEFAULT_ITERATIONS, self.salt, self.nonce)
+    }
+}
+
+/// Encrypt with the given parameters. The salt and nonce must never be reused.
+pub fn seal_with(
+    plain: &[u8],
+    passphrase: &str,
+    iterations: u32,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(plain.len() + SEAL_OVERHEAD);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&[VERSION, KDF_PBKDF2_SHA256, 0, 0]);
+    out.extend_from_slice(&iterations.to_le_bytes());
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, iterations));
+    let sealed = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad: &out })
+        .expect("payload within ChaCha20-Poly1305 limits");
+    out.extend_from_slice(&sealed);
+    out
+}
+
+/// Decrypt and authenticate an encrypted export.
+pub fn open(data: &[u8], passphrase: &str) -> Result<Vec<u8>, CryptoError> {
+    if data.len() < HEADER_LEN + TAG_LEN || !is_encrypted(data) {
+        return Err(CryptoError::Corrupt);
+    }
+    if data[4] != VERSION {
+        return Err(CryptoError::UnsupportedVersion(data[4]));
+    }
+    if data[5] != KDF_PBKDF2_SHA256 {
+        return Err(CryptoError::UnsupportedKdf(data[5]));
+    }
+    let iterations = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(CryptoError::Corrupt);
+    }
+    let (header, sealed) = data.split_at(HEADER_LEN);
+    let salt = &header[12..12 + SALT_LEN];
+    let nonce = &header[12 + SALT_LEN..];
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt, iterations));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: header })
+        .map_err(|_| CryptoError::WrongPassphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_exports_round_trip() {
+        let plain = "#name:Secret\nhola\thello\n".as_bytes();
+        let sealed = seal_with(plain, "correct horse", 1000, [7; SALT_LEN], [9; NONCE_LEN]);
+        assert!(is_encrypted(&sealed));
+        assert_eq!(sealed.len(), plain.len() + SEAL_OVERHEAD);
+        assert_eq!(open(&sealed, "correct horse").unwrap(), plain);
+        assert_eq!(open(&sealed, "correct horsE"), Err(CryptoError::WrongPassphrase));
+
+        // The header is authenticated too
+        let mut altered = sealed.clone();
+        altered[HEADER_LEN - 1] ^= 1;
+        assert_eq!(open(&altered, "correct horse"), Err(CryptoError::WrongPassphrase));
+        let mut altered = sealed.clone();
+        altered[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(open(&altered, "correct horse"), Err(CryptoError::Corrupt));
+        assert_eq!(open(&sealed[..HEADER_LEN], "correct horse"), Err(CryptoError::Corrupt));
+    }
+
+    #[test]
+    fn matches_the_host_tool() {
+        // fccrypt.seal(plain, "correct horse", 1000, bytes([7] * 16), bytes([9] * 12))
+        let expected = "4643454e01010000e803000007070707070707070707070707070707090909090909090909090909\
+                        935ffc81d7ee79650cc7808b9bdbe7a5f3306c34339568e420dcfea021fe5a5c5a4556d295f8acda";
+        let data: Vec<u8> =
+            (0..expected.len()).step_by(2).map(|i| u8::from_str_radix(&expected[i..i + 2], 16).unwrap()).collect();
+        let plain = b"#name:Secret\nhola\thello\n";
+        assert_eq!(seal_with(plain, "correct horse", 1000, [7; SALT_LEN], [9; NONCE_LEN]), data);
+        assert_eq!(open(&data, "correct horse").unwrap(), plain);
+    }
+}
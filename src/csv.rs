@@ -0,0 +1,242 @@
+//! RFC 4180 comma-separated values, for decks kept in spreadsheets.
+//!
+//! Records are `front,back[,id]`. Fields containing commas, quotes or line
+//! breaks are wrapped in double quotes, with any quote inside doubled, and
+//! quoted text is kept byte for byte. Unquoted fields are trimmed, for files
+//! typed by hand. As in TSV, a `#name:` line names the deck and other `#`
+//! lines are comments.
+
+use crate::deck::Card;
+use crate::import::{CardCollector, ImportResult, SkipReason, escape_field, parse_card_id, unescape_field};
+use crate::storage::valid_deck_name;
+
+/// A line starting with `#`, or a record of fields.
+pub enum Row {
+    Comment(String),
+    Fields(Vec<String>),
+}
+
+/// Parse CSV data into cards. A first record of `front,back` is taken as a
+/// spreadsheet header row and skipped.
+pub fn parse_csv(data: &str) -> ImportResult {
+    let mut name = None;
+    let mut collector = CardCollector::new();
+    let (rows, unterminated) = read_rows(data, ',');
+    let mut first_record = true;
+
+    for (line, row) in rows {
+        let fields = match row {
+            Row::Comment(text) => {
+                if let Some(n) = text.strip_prefix("#name:") {
+                    let n = unescape_field(n.trim_matches(' '));
+                    if valid_deck_name(&n) {
+                        name = Some(n);
+                    } else {
+                        collector.skip(line, SkipReason::InvalidName);
+                    }
+                }
+                continue;
+            }
+            Row::Fields(fields) => fields,
+        };
+        if fields.len() == 1 && fields[0].is_empty() {
+            continue;
+        }
+        let is_header = first_record
+            && fields.len() >= 2
+            && fields[0].eq_ignore_ascii_case("front")
+            && fields[1].eq_ignore_ascii_case("back");
+        first_record = false;
+        if is_header {
+            continue;
+        }
+        if fields.len() < 2 {
+            collector.skip(line, SkipReason::MissingField);
+            continue;
+        }
+        let id = fields.get(2).and_then(|f| parse_card_id(f)).unwrap_or(0);
+        collector.add(line, &fields[0], &fields[1], id, Vec::new());
+    }
+    if let Some(line) = unterminated {
+        collector.skip(line, SkipReason::UnterminatedQuote);
+    }
+    collector.finish(name)
+}
+
+/// Split CSV text into rows, each with the line number it starts on.
+/// `separator` is normally a comma; Anki exports may use others.
+/// Unquoted fields are trimmed; quoted ones, including any line breaks
+/// inside them, are kept exactly. Spaces before an opening quote are
+/// ignored. Also returns the starting line of a record whose quoted field
+/// was never closed; that record is dropped.
+pub fn read_rows(data: &str, separator: char) -> (Vec<(usize, Row)>, Option<usize>) {
+    let mut rows = Vec::new();
+    let mut chars = data.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        let start = line;
+        if c == '#' {
+            let mut text = String::new();
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    line += 1;
+                    break;
+                }
+                text.push(c);
+            }
+            rows.push((start, Row::Comment(text.trim_end_matches('\r').to_string())));
+            continue;
+        }
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let finish = |field: String, quoted: bool| if quoted { field } else { field.trim().to_string() };
+        loop {
+            match chars.next() {
+                None if in_quotes => return (rows, Some(start)),
+                None => {
+                    fields.push(finish(field, quoted));
+                    break;
+                }
+                Some('"') if in_quotes => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                    }
+                }
+                Some('"') if !quoted && field.trim_matches(' ').is_empty() => {
+                    field.clear();
+                    quoted = true;
+                    in_quotes = true;
+                }
+                Some('\n') if in_quotes => {
+                    line += 1;
+                    field.push('\n');
+                }
+                Some('\r') if !in_quotes && chars.peek() == Some(&'\n') => {}
+                Some('\n') => {
+                    line += 1;
+                    fields.push(finish(field, quoted));
+                    break;
+                }
+                Some(c) if c == separator && !in_quotes => {
+                    fields.push(finish(std::mem::take(&mut field), quoted));
+                    quoted = false;
+                }
+                Some(c) => field.push(c),
+            }
+        }
+        rows.push((start, Row::Fields(fields)));
+    }
+    (rows, None)
+}
+
+/// Export cards as CSV with CRLF line endings, with each card's id in a
+/// third column. The deck name is escaped as in TSV, so one with a line
+/// break can't spill into the records.
+pub fn cards_to_csv(name: &str, cards: &[Card]) -> String {
+    let mut output = format!("#name:{}\r\n", escape_field(name));
+    for card in cards {
+        output.push_str(&quote_field(&card.front));
+        output.push(',');
+        output.push_str(&quote_field(&card.back));
+        output.push_str(&format!(",{:016x}\r\n", card.id));
+    }
+    output
+}
+
+/// Quote a field if it would otherwise be misread: separators, quotes, line
+/// breaks, a leading `#` that would make the record a comment, or
+/// surrounding whitespace that would be trimmed.
+fn quote_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) || field.starts_with('#') || field.trim() != field {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::assign_ids;
+    use crate::import::ParseIssue;
+
+    fn fronts_and_backs(result: &ImportResult) -> Vec<(&str, &str)> {
+        result.cards.iter().map(|c| (c.front.as_str(), c.back.as_str())).collect()
+    }
+
+    #[test]
+    fn quoted_fields_parse() {
+        let result = parse_csv("\"a, b\",c\n\"say \"\"hi\"\"\",\"\"\"\"\n\"two\nlines\",x\n");
+        assert_eq!(result.report.issues, vec![]);
+        assert_eq!(fronts_and_backs(&result), [("a, b", "c"), ("say \"hi\"", "\""), ("two\nlines", "x")]);
+        // The record after a multi-line field reports the line it starts on
+        let result = parse_csv("\"one\ntwo\",x\nonly\n");
+        assert_eq!(result.report.issues, vec![ParseIssue { line: 3, reason: SkipReason::MissingField }]);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let result = parse_csv("#name:Deck\r\nfront,back\r\nhola,hello\r\n\"a\r\nb\",c\r\n");
+        assert_eq!(result.name.as_deref(), Some("Deck"));
+        assert_eq!(fronts_and_backs(&result), [("hola", "hello"), ("a\r\nb", "c")]);
+    }
+
+    #[test]
+    fn only_unquoted_fields_are_trimmed() {
+        let result = parse_csv("  hola ,\thello \n\" lead\",\"trail \"\nx, \"quoted, after space\"\n");
+        assert_eq!(
+            fronts_and_backs(&result),
+            [("hola", "hello"), (" lead", "trail "), ("x", "quoted, after space")]
+        );
+    }
+
+    #[test]
+    fn bad_records_are_reported() {
+        let result = parse_csv("front,back\nlonely\n,back\na,\"open\n");
+        assert_eq!(
+            result.report.issues,
+            vec![
+                ParseIssue { line: 2, reason: SkipReason::MissingField },
+                ParseIssue { line: 3, reason: SkipReason::EmptyFront },
+                ParseIssue { line: 4, reason: SkipReason::UnterminatedQuote },
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let texts = [
+            "plain", " lead", "trail ", "a,b", "\"quoted\"", "two\nlines", "crlf\r\nbreak", "#hash", "x\u{a0}",
+            "tab\there", "é漢",
+        ];
+        let mut cards: Vec<Card> = texts
+            .iter()
+            .flat_map(|front| texts.iter().map(move |back| Card::new(front, back)))
+            .collect();
+        assign_ids(&mut cards);
+        for name in ["Spanish", "#hash", " padded ", "back\\slash"] {
+            let csv = cards_to_csv(name, &cards);
+            let header = csv.split("\r\n").next().unwrap();
+            assert!(!header.contains('\n'), "{:?}", header);
+            let result = parse_csv(&csv);
+            assert_eq!(result.report.issues, vec![], "{:?}", csv);
+            assert_eq!(result.name.as_deref(), Some(name));
+            assert_eq!(result.cards.len(), cards.len());
+            for (parsed, card) in result.cards.iter().zip(&cards) {
+                assert_eq!((parsed.id, &parsed.front, &parsed.back), (card.id, &card.front, &card.back));
+            }
+        }
+
+        // An escaped line break would split the deck index, so the name is dropped
+        let result = parse_csv(&cards_to_csv("two\nlines", &cards));
+        assert_eq!(result.name, None);
+        assert_eq!(result.report.issues, vec![ParseIssue { line: 1, reason: SkipReason::InvalidName }]);
+    }
+}
@@ -1,7 +1,21 @@
+use std::collections::BTreeSet;
+
 #[derive(Clone)]
 pub struct Card {
+    /// Stable identity used to key review state and match re-imports.
+    /// Zero means not yet assigned; see `assign_ids`.
+    pub id: u64,
     pub front: String,
     pub back: String,
+    /// Labels carried over from an Anki export; never contain spaces.
+    pub tags: Vec<String>,
+}
+
+impl Card {
+    /// A card without an id or tags yet.
+    pub fn new(front: &str, back: &str) -> Self {
+        Self { id: 0, front: front.to_string(), back: back.to_string(), tags: Vec::new() }
+    }
 }
 
 pub struct DeckMeta {
@@ -9,55 +23,358 @@ pub struct DeckMeta {
     pub card_count: u32,
 }
 
+/// Magic bytes at the start of every versioned deck key.
+const DECK_MAGIC: [u8; 4] = *b"FCDK";
+/// Current deck format version. Decks with a newer version are refused
+/// rather than misread.
+pub const FORMAT_VERSION: u8 = 4;
+/// [4: magic "FCDK"][u8: version][u8: flags][u16: reserved]
+pub const HEADER_LEN: usize = 8;
+/// Upper bound on a single front or back, to keep a corrupt length from
+/// exhausting memory on load.
+pub const MAX_FIELD_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FormatError {
+    /// A card field is longer than `MAX_FIELD_BYTES`.
+    FieldTooLong { card: usize, len: usize },
+    /// Stored data was written by a newer version of the app.
+    UnsupportedVersion(u8),
+    /// Stored data is truncated or not valid UTF-8.
+    Corrupt,
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::FieldTooLong { card, len } => {
+                write!(f, "card {} has a {} byte field (max {})", card + 1, len, MAX_FIELD_BYTES)
+            }
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported deck format version {}", v),
+            FormatError::Corrupt => write!(f, "deck data is corrupt"),
+        }
+    }
+}
+
 /// Serialize a deck's cards to binary format:
-/// [u32: card_count] { [u16: front_len][front_utf8] [u16: back_len][back_utf8] } ...
-pub fn serialize_cards(cards: &[Card]) -> Vec<u8> {
+/// [header] [u32: card_count]
+///   { [u64: id][varint: front_len][front_utf8] [varint: back_len][back_utf8] [varint: tags_len][tags_utf8] } ...
+/// Tags are stored space-separated.
+/// Lengths are LEB128 varints, so fields of any size up to `MAX_FIELD_BYTES` encode exactly.
+pub fn serialize_cards(cards: &[Card]) -> Result<Vec<u8>, FormatError> {
     let mut buf = Vec::new();
+    buf.extend_from_slice(&DECK_MAGIC);
+    buf.push(FORMAT_VERSION);
+    buf.push(0); // flags
+    buf.extend_from_slice(&[0, 0]);
     let count = cards.len() as u32;
     buf.extend_from_slice(&count.to_le_bytes());
-    for card in cards {
-        let front_bytes = card.front.as_bytes();
-        let back_bytes = card.back.as_bytes();
-        buf.extend_from_slice(&(front_bytes.len() as u16).to_le_bytes());
-        buf.extend_from_slice(front_bytes);
-        buf.extend_from_slice(&(back_bytes.len() as u16).to_le_bytes());
-        buf.extend_from_slice(back_bytes);
+    for (i, card) in cards.iter().enumerate() {
+        buf.extend_from_slice(&card.id.to_le_bytes());
+        let tags = card.tags.join(" ");
+        for field in [&card.front, &card.back, &tags] {
+            let bytes = field.as_bytes();
+            if bytes.len() > MAX_FIELD_BYTES {
+                return Err(FormatError::FieldTooLong { card: i, len: bytes.len() });
+            }
+            write_varint(&mut buf, bytes.len() as u32);
+            buf.extend_from_slice(bytes);
+        }
+    }
+    Ok(buf)
+}
+
+pub fn write_varint(buf: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub fn read_varint(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut v: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        v |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(v);
+        }
     }
-    buf
+    None
 }
 
-/// Deserialize cards from binary format.
-pub fn deserialize_cards(data: &[u8]) -> Option<Vec<Card>> {
+/// Format version of serialized deck data; 0 for the original headerless layout.
+/// The legacy layout starts with a little-endian card count, which can never
+/// spell the magic bytes for a realistic deck size.
+pub fn format_version(data: &[u8]) -> u8 {
+    if data.len() >= HEADER_LEN && data[0..4] == DECK_MAGIC {
+        data[4]
+    } else {
+        0
+    }
+}
+
+/// Card count from the start of serialized deck data, without decoding the cards.
+pub fn read_card_count(data: &[u8]) -> Option<u32> {
+    let pos = if format_version(data) == 0 { 0 } else { HEADER_LEN };
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Deserialize cards from binary format, accepting the current layout as
+/// well as version 3 (no tags), version 2 (no ids or tags), version 1 and
+/// the original headerless one (both with u16 lengths). Cards from formats
+/// without ids are given content-derived ids.
+pub fn deserialize_cards(data: &[u8]) -> Result<Vec<Card>, FormatError> {
+    let version = format_version(data);
+    let layout = match version {
+        0 | 1 => Layout { varint_lengths: false, ids: false, tags: false },
+        2 => Layout { varint_lengths: true, ids: false, tags: false },
+        3 => Layout { varint_lengths: true, ids: true, tags: false },
+        FORMAT_VERSION => Layout { varint_lengths: true, ids: true, tags: true },
+        v => return Err(FormatError::UnsupportedVersion(v)),
+    };
+    let body = if version == 0 { data } else { &data[HEADER_LEN..] };
+    let cards = deserialize_card_list(body, &layout);
+    let mut cards = cards.ok_or(FormatError::Corrupt)?;
+    assign_ids(&mut cards);
+    Ok(cards)
+}
+
+/// Which fields a deck format version has.
+struct Layout {
+    /// Varint field lengths rather than u16.
+    varint_lengths: bool,
+    ids: bool,
+    tags: bool,
+}
+
+/// Decode [u32: card_count] { [u64: id][len][front_utf8] [len][back_utf8] [len][tags_utf8] } ...
+/// leaving out whatever the layout doesn't have.
+fn deserialize_card_list(data: &[u8], layout: &Layout) -> Option<Vec<Card>> {
     if data.len() < 4 {
         return None;
     }
     let count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
-    let mut cards = Vec::with_capacity(count);
-    let mut pos = 4;
-    for _ in 0..count {
-        if pos + 2 > data.len() {
+    let mut cards = Vec::with_capacity(count.min(data.len() / 4));
+    let mut pos: usize = 4;
+    let read_field = |pos: &mut usize| -> Option<String> {
+        // Checked, so a corrupt length can't wrap the position on 32-bit
+        let len = if layout.varint_lengths {
+            read_varint(data, pos)? as usize
+        } else {
+            let end = pos.checked_add(2)?;
+            let bytes = data.get(*pos..end)?;
+            *pos = end;
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+        };
+        if len > MAX_FIELD_BYTES {
             return None;
         }
-        let front_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-        pos += 2;
-        if pos + front_len > data.len() {
-            return None;
+        let end = pos.checked_add(len)?;
+        let field = String::from_utf8(data.get(*pos..end)?.to_vec()).ok()?;
+        *pos = end;
+        Some(field)
+    };
+    for _ in 0..count {
+        let id = if layout.ids {
+            let bytes = data.get(pos..pos.checked_add(8)?)?;
+            pos += 8;
+            u64::from_le_bytes(bytes.try_into().ok()?)
+        } else {
+            0
+        };
+        let front = read_field(&mut pos)?;
+        let back = read_field(&mut pos)?;
+        let tags = if layout.tags {
+            read_field(&mut pos)?.split_whitespace().map(str::to_string).collect()
+        } else {
+            Vec::new()
+        };
+        cards.push(Card { id, front, back, tags });
+    }
+    Some(cards)
+}
+
+/// Give every card without an id, or with an id already taken by an earlier
+/// card, one derived from its front text. The same text always yields the
+/// same id, so an unchanged file imported twice produces the same ids;
+/// duplicate fronts are told apart by a salt.
+pub fn assign_ids(cards: &mut [Card]) {
+    let mut used = BTreeSet::new();
+    let mut needs_id = Vec::new();
+    for (i, card) in cards.iter().enumerate() {
+        if card.id == 0 || !used.insert(card.id) {
+            needs_id.push(i);
+        }
+    }
+    for i in needs_id {
+        let mut salt = 0;
+        let mut id = content_id(&cards[i].front, salt);
+        while id == 0 || used.contains(&id) {
+            salt += 1;
+            id = content_id(&cards[i].front, salt);
         }
-        let front = String::from_utf8(data[pos..pos + front_len].to_vec()).ok()?;
-        pos += front_len;
+        used.insert(id);
+        cards[i].id = id;
+    }
+}
 
-        if pos + 2 > data.len() {
-            return None;
+/// 64-bit FNV-1a hash of the salt and text.
+pub fn content_id(text: &str, salt: u32) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in salt.to_le_bytes().iter().chain(text.as_bytes()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cards as the layouts without tags stored them: hola/hello and adiós/goodbye.
+    const CARDS: [(&str, &str); 2] = [("hola", "hello"), ("adiós", "goodbye")];
+
+    fn header(version: u8) -> Vec<u8> {
+        let mut buf = DECK_MAGIC.to_vec();
+        buf.extend_from_slice(&[version, 0, 0, 0]);
+        buf
+    }
+
+    /// The original layout: [u32: count] { [u16: len][front] [u16: len][back] } ...
+    fn u16_body() -> Vec<u8> {
+        let mut buf = (CARDS.len() as u32).to_le_bytes().to_vec();
+        for (front, back) in CARDS {
+            for field in [front, back] {
+                buf.extend_from_slice(&(field.len() as u16).to_le_bytes());
+                buf.extend_from_slice(field.as_bytes());
+            }
         }
-        let back_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-        pos += 2;
-        if pos + back_len > data.len() {
-            return None;
+        buf
+    }
+
+    /// Version 2 and 3 bodies: varint lengths, with ids from version 3.
+    fn varint_body(ids: bool) -> Vec<u8> {
+        let mut buf = (CARDS.len() as u32).to_le_bytes().to_vec();
+        for (i, (front, back)) in CARDS.iter().enumerate() {
+            if ids {
+                buf.extend_from_slice(&(100 + i as u64).to_le_bytes());
+            }
+            for field in [front, back] {
+                write_varint(&mut buf, field.len() as u32);
+                buf.extend_from_slice(field.as_bytes());
+            }
         }
-        let back = String::from_utf8(data[pos..pos + back_len].to_vec()).ok()?;
-        pos += back_len;
+        buf
+    }
 
-        cards.push(Card { front, back });
+    fn assert_cards(cards: &[Card]) {
+        let texts: Vec<(&str, &str)> = cards.iter().map(|c| (c.front.as_str(), c.back.as_str())).collect();
+        assert_eq!(texts, CARDS);
+        assert!(cards.iter().all(|c| c.tags.is_empty()));
+    }
+
+    #[test]
+    fn legacy_decks_migrate() {
+        let headerless = u16_body();
+        let v1 = [header(1), u16_body()].concat();
+        let v2 = [header(2), varint_body(false)].concat();
+        for data in [&headerless, &v1, &v2] {
+            let cards = deserialize_cards(data).unwrap();
+            assert_cards(&cards);
+            // Formats without ids get the ids the same text always gets
+            assert_eq!(cards[0].id, content_id("hola", 0));
+            assert_eq!(cards[1].id, content_id("adiós", 0));
+        }
+        assert_eq!(format_version(&headerless), 0);
+        assert_eq!(format_version(&v2), 2);
+
+        let cards = deserialize_cards(&[header(3), varint_body(true)].concat()).unwrap();
+        assert_cards(&cards);
+        assert_eq!((cards[0].id, cards[1].id), (100, 101));
+    }
+
+    #[test]
+    fn card_count_reads_either_layout() {
+        assert_eq!(read_card_count(&u16_body()), Some(2));
+        assert_eq!(read_card_count(&[header(3), varint_body(true)].concat()), Some(2));
+        let current = serialize_cards(&vec![Card::new("a", "b"); 3]).unwrap();
+        assert_eq!(read_card_count(&current[..HEADER_LEN + 4]), Some(3));
+        assert_eq!(read_card_count(&current[..HEADER_LEN + 3]), None);
+        assert_eq!(read_card_count(&[1, 0]), None);
+    }
+
+    #[test]
+    fn damaged_decks_are_corrupt() {
+        let current = serialize_cards(&[Card::new("hola", "hello"), Card::new("adiós", "goodbye")]).unwrap();
+        let v1 = [header(1), u16_body()].concat();
+        for data in [&current, &v1] {
+            // Every cut through the header, count or a card is caught
+            for len in 0..data.len() {
+                assert!(matches!(deserialize_cards(&data[..len]), Err(FormatError::Corrupt)), "cut at {}", len);
+            }
+        }
+
+        let mut bad_utf8 = current.clone();
+        let at = bad_utf8.iter().position(|&b| b == b'h').unwrap();
+        bad_utf8[at] = 0xff;
+        assert!(matches!(deserialize_cards(&bad_utf8), Err(FormatError::Corrupt)));
+
+        let mut huge_count = current.clone();
+        huge_count[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(deserialize_cards(&huge_count), Err(FormatError::Corrupt)));
+
+        let newer = [header(FORMAT_VERSION + 1), varint_body(true)].concat();
+        match deserialize_cards(&newer) {
+            Err(FormatError::UnsupportedVersion(v)) => assert_eq!(v, FORMAT_VERSION + 1),
+            other => panic!("expected UnsupportedVersion, got {:?}", other.map(|c| c.len())),
+        }
+    }
+
+    #[test]
+    fn long_fields_round_trip() {
+        let mut cards = vec![Card::new(&"x".repeat(70_000), "short"), Card::new("é", &"漢".repeat(30_000))];
+        cards[1].tags = vec!["big".to_string(), "cjk".to_string()];
+        assign_ids(&mut cards);
+        let data = serialize_cards(&cards).unwrap();
+        let decoded = deserialize_cards(&data).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (decoded, card) in decoded.iter().zip(&cards) {
+            assert_eq!((decoded.id, &decoded.front, &decoded.back), (card.id, &card.front, &card.back));
+            assert_eq!(decoded.tags, card.tags);
+        }
+    }
+
+    #[test]
+    fn oversized_fields_are_refused() {
+        let cards = vec![Card::new("ok", "ok"), Card::new("ok", &"x".repeat(MAX_FIELD_BYTES + 1))];
+        match serialize_cards(&cards) {
+            Err(FormatError::FieldTooLong { card: 1, len }) => assert_eq!(len, MAX_FIELD_BYTES + 1),
+            other => panic!("expected FieldTooLong, got {:?}", other.map(|d| d.len())),
+        }
+
+        // A stored length over the limit is refused before anything is read
+        let mut data = serialize_cards(&[Card::new("a", "b")]).unwrap();
+        data.truncate(HEADER_LEN + 4 + 8);
+        write_varint(&mut data, MAX_FIELD_BYTES as u32 + 1);
+        assert!(matches!(deserialize_cards(&data), Err(FormatError::Corrupt)));
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for v in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, MAX_FIELD_BYTES as u32, u32::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, v);
+            let mut pos = 0;
+            assert_eq!(read_varint(&buf, &mut pos), Some(v));
+            assert_eq!(pos, buf.len());
+            assert_eq!(read_varint(&buf[..buf.len() - 1], &mut 0), None);
+        }
+        // A varint longer than five bytes doesn't decode
+        assert_eq!(read_varint(&[0xff; 6], &mut 0), None);
     }
-    Some(cards)
 }
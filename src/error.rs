@@ -0,0 +1,63 @@
+use std::fmt;
+
+use crate::backup::BackupError;
+use crate::crypto::CryptoError;
+use crate::deck::FormatError;
+
+/// Errors from storage, import and export, shown to the user on screen.
+#[derive(Debug)]
+pub enum Error {
+    /// A PDDB key couldn't be opened, read or written.
+    Pddb(std::io::Error),
+    /// Card data couldn't be encoded, or stored data couldn't be decoded.
+    Format(FormatError),
+    /// Imported text contained nothing usable.
+    Parse(String),
+    /// A TCP import or export connection failed.
+    Network(std::io::Error),
+    /// A backup sent for restore failed its checks.
+    Backup(BackupError),
+    /// A sender connected without the pairing code; holds its address.
+    Pairing(String),
+    /// An encrypted export couldn't be opened.
+    Crypto(CryptoError),
+    /// The TRNG couldn't supply random bytes for a pairing code or an
+    /// encrypted export's salt and nonce.
+    Random,
+    /// A deck name the newline-separated index can't hold.
+    DeckName(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Pddb(e) => write!(f, "Storage error: {}", e),
+            Error::Format(e) => write!(f, "Deck data error: {}", e),
+            Error::Parse(msg) => write!(f, "Import error: {}", msg),
+            Error::Network(e) => write!(f, "Network error: {}", e),
+            Error::Backup(e) => write!(f, "Restore refused: {}", e),
+            Error::Pairing(sender) => write!(f, "Rejected {}: wrong pairing code", sender),
+            Error::Crypto(e) => write!(f, "Decryption failed: {}", e),
+            Error::Random => write!(f, "Random number generator unavailable"),
+            Error::DeckName(name) => write!(f, "Invalid deck name {:?}", name),
+        }
+    }
+}
+
+impl From<FormatError> for Error {
+    fn from(e: FormatError) -> Self {
+        Error::Format(e)
+    }
+}
+
+impl From<BackupError> for Error {
+    fn from(e: BackupError) -> Self {
+        Error::Backup(e)
+    }
+}
+
+impl From<CryptoError> for Error {
+    fn from(e: CryptoError) -> Self {
+        Error::Crypto(e)
+    }
+}
@@ -0,0 +1,284 @@
+//! FSRS (Free Spaced Repetition Scheduler) memory model in Q16.16 fixed point.
+//!
+//! The Precursor CPU has no FPU, so stability, difficulty and retrievability
+//! are computed with integer arithmetic. Desired retention is fixed at 90%,
+//! where the FSRS forgetting curve makes the next interval equal to the
+//! stability in days.
+
+use crate::scheduler::{CardState, Grade, Scheduler};
+
+const FRAC_BITS: u32 = 16;
+const ONE: i64 = 1 << FRAC_BITS;
+/// Internal precision for the exp/ln series.
+const HI_BITS: u32 = 30;
+const HI_ONE: i64 = 1 << HI_BITS;
+const LN2_HI: i64 = 744_261_118; // ln(2) in Q2.30
+
+const MAX_INTERVAL: u32 = 36500;
+const MIN_DIFFICULTY: i64 = ONE;
+const MAX_DIFFICULTY: i64 = 10 * ONE;
+/// Forgetting curve R(t, S) = (1 + FACTOR * t / S) ^ DECAY with DECAY = -0.5.
+const FACTOR: i64 = 19 * ONE / 81;
+const DECAY: i64 = -ONE / 2;
+
+/// FSRS-4.5 default parameters, in Q16.16.
+const DEFAULT_WEIGHTS: [i64; 17] = [
+    31929,  // 0.4872  initial stability, Again
+    91770,  // 1.4003  initial stability, Hard
+    243433, // 3.7145  initial stability, Good
+    905747, // 13.8206 initial stability, Easy
+    338284, // 5.1618  initial difficulty
+    80596,  // 1.2298  difficulty per grade
+    58819,  // 0.8975  difficulty change per review
+    2032,   // 0.031   difficulty mean reversion
+    107964, // 1.6474  recall stability scale (exp)
+    8959,   // 0.1367  recall stability decay
+    68557,  // 1.0461  recall retrievability factor
+    138097, // 2.1072  forget stability scale
+    5197,   // 0.0793  forget difficulty exponent
+    21273,  // 0.3246  forget stability exponent
+    104006, // 1.587   forget retrievability factor
+    14890,  // 0.2272  hard penalty
+    188449, // 2.8755  easy bonus
+];
+
+pub struct Fsrs {
+    w: [i64; 17],
+}
+
+impl Fsrs {
+    pub fn new() -> Self {
+        Self { w: DEFAULT_WEIGHTS }
+    }
+
+    fn grade_index(grade: Grade) -> i64 {
+        match grade {
+            Grade::Again => 1,
+            Grade::Hard => 2,
+            Grade::Good => 3,
+            Grade::Easy => 4,
+        }
+    }
+
+    fn initial_difficulty(&self, g: i64) -> i64 {
+        clamp_difficulty(self.w[4] - (g - 3) * self.w[5])
+    }
+
+    /// FSRS-4.5 reverts difficulty towards D0(Good), which is `w[4]`.
+    fn next_difficulty(&self, d: i64, g: i64) -> i64 {
+        let d = d - (g - 3) * self.w[6];
+        clamp_difficulty(mul(self.w[7], self.w[4]) + mul(ONE - self.w[7], d))
+    }
+
+    /// Probability of recall after `elapsed` days at stability `s`.
+    fn retrievability(&self, elapsed: u32, s: i64) -> i64 {
+        let base = ONE + div(FACTOR * elapsed as i64, s);
+        pow(base, DECAY)
+    }
+
+    fn recall_stability(&self, d: i64, s: i64, r: i64, g: i64) -> i64 {
+        let mut inc = mul(exp(self.w[8]), 11 * ONE - d);
+        inc = mul(inc, pow(s, -self.w[9]));
+        inc = mul(inc, exp(mul(self.w[10], ONE - r)) - ONE);
+        if g == 2 {
+            inc = mul(inc, self.w[15]);
+        } else if g == 4 {
+            inc = mul(inc, self.w[16]);
+        }
+        mul(s, ONE + inc)
+    }
+
+    fn forget_stability(&self, d: i64, s: i64, r: i64) -> i64 {
+        let mut s2 = mul(self.w[11], pow(d, -self.w[12]));
+        s2 = mul(s2, pow(s + ONE, self.w[13]) - ONE);
+        mul(s2, exp(mul(self.w[14], ONE - r)))
+    }
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn review(&self, state: &CardState, grade: Grade, today: u32) -> CardState {
+        let g = Self::grade_index(grade);
+        let (s, d) = if state.stability == 0 {
+            (self.w[g as usize - 1], self.initial_difficulty(g))
+        } else {
+            let s = state.stability as i64;
+            let d = state.difficulty as i64;
+            let last_review = state.due.saturating_sub(state.interval);
+            let r = self.retrievability(today.saturating_sub(last_review), s);
+            let s2 = if grade == Grade::Again {
+                self.forget_stability(d, s, r)
+            } else {
+                self.recall_stability(d, s, r, g)
+            };
+            (s2, self.next_difficulty(d, g))
+        };
+        let s = s.clamp(1, MAX_INTERVAL as i64 * ONE);
+        let interval = next_interval(s);
+        CardState {
+            interval,
+            reps: if grade == Grade::Again { 0 } else { state.reps + 1 },
+            due: today + interval,
+            stability: s as u32,
+            difficulty: d as u32,
+            ..*state
+        }
+    }
+}
+
+/// Interval in whole days for a stability at 90% desired retention.
+fn next_interval(s: i64) -> u32 {
+    (((s + ONE / 2) >> FRAC_BITS) as u32).clamp(1, MAX_INTERVAL)
+}
+
+fn clamp_difficulty(d: i64) -> i64 {
+    d.clamp(MIN_DIFFICULTY, MAX_DIFFICULTY)
+}
+
+fn mul(a: i64, b: i64) -> i64 {
+    (a * b + (ONE >> 1)) >> FRAC_BITS
+}
+
+fn div(a: i64, b: i64) -> i64 {
+    (a << FRAC_BITS) / b
+}
+
+/// e^x for Q16.16 `x`, saturating for large arguments.
+fn exp(x: i64) -> i64 {
+    if x < -20 * ONE {
+        return 0;
+    }
+    let x = x.min(20 * ONE);
+    // x = k*ln2 + r with |r| <= ln2/2, so e^x = 2^k * e^r
+    let x_hi = x << (HI_BITS - FRAC_BITS);
+    let k = (x_hi + LN2_HI / 2).div_euclid(LN2_HI);
+    let r = x_hi - k * LN2_HI;
+    let mut term = HI_ONE;
+    let mut sum = HI_ONE;
+    for n in 1..12 {
+        term = ((term * r) >> HI_BITS) / n;
+        sum += term;
+    }
+    let shift = k + FRAC_BITS as i64 - HI_BITS as i64;
+    if shift >= 0 { sum << shift } else { sum >> -shift }
+}
+
+/// Natural log for positive Q16.16 `x`.
+fn ln(x: i64) -> i64 {
+    debug_assert!(x > 0);
+    // x = m * 2^k with m in [1, 2)
+    let k = 63 - x.leading_zeros() as i64 - FRAC_BITS as i64;
+    let m = if k >= 0 {
+        (x << (HI_BITS - FRAC_BITS)) >> k
+    } else {
+        (x << (HI_BITS - FRAC_BITS)) << -k
+    };
+    // ln(m) = 2 * atanh(z), z = (m - 1) / (m + 1) <= 1/3
+    let z = ((m - HI_ONE) << HI_BITS) / (m + HI_ONE);
+    let z2 = (z * z) >> HI_BITS;
+    let mut power = z;
+    let mut sum = 0;
+    for n in 0..10 {
+        sum += power / (2 * n + 1);
+        power = (power * z2) >> HI_BITS;
+    }
+    (2 * sum + k * LN2_HI) >> (HI_BITS - FRAC_BITS)
+}
+
+/// x^y for positive Q16.16 `x`.
+fn pow(x: i64, y: i64) -> i64 {
+    exp(mul(y, ln(x)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_fixed(v: f64) -> i64 {
+        (v * ONE as f64).round() as i64
+    }
+
+    fn to_f64(v: i64) -> f64 {
+        v as f64 / ONE as f64
+    }
+
+    /// Grade a new card through `grades`, reviewing each time on its due day
+    /// plus any extra delay, and collect the scheduled intervals.
+    fn intervals(grades: &[Grade], delays: &[u32]) -> Vec<u32> {
+        let fsrs = Fsrs::new();
+        let mut state = CardState::new();
+        let mut today = 0;
+        let mut out = Vec::new();
+        for (i, &grade) in grades.iter().enumerate() {
+            state = fsrs.review(&state, grade, today);
+            out.push(state.interval);
+            today = state.due + delays.get(i).copied().unwrap_or(0);
+        }
+        out
+    }
+
+    /// Reference intervals come from a separate floating point implementation
+    /// of the FSRS-4.5 formulas, as in py-fsrs 4.x, with the same default
+    /// weights. Fixed point rounding may shift a long interval by a day or so.
+    fn assert_close(actual: &[u32], expected: &[u32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            let tolerance = (*e / 100).max(1);
+            assert!(a.abs_diff(*e) <= tolerance, "got {:?}, expected {:?}", actual, expected);
+        }
+    }
+
+    use Grade::{Again, Easy, Good, Hard};
+
+    #[test]
+    fn fixed_point_primitives() {
+        for &x in &[0.25f64, 0.5, 1.0, 3.7, 12.0, 900.0] {
+            assert!((to_f64(exp(to_fixed(x.ln()))) - x).abs() / x < 1e-4);
+            assert!((to_f64(ln(to_fixed(x))) - x.ln()).abs() < 1e-4);
+        }
+        assert!((to_f64(pow(to_fixed(2.0), -ONE / 2)) - 0.5f64.sqrt()).abs() < 1e-4);
+        assert_eq!(exp(-30 * ONE), 0);
+    }
+
+    #[test]
+    fn retrievability_is_ninety_percent_at_stability() {
+        let fsrs = Fsrs::new();
+        let r = fsrs.retrievability(10, 10 * ONE);
+        assert!((to_f64(r) - 0.9).abs() < 1e-3);
+        assert_eq!(fsrs.retrievability(0, 10 * ONE), ONE);
+    }
+
+    #[test]
+    fn first_review_uses_initial_stability() {
+        assert_eq!(intervals(&[Again], &[]), vec![1]);
+        assert_eq!(intervals(&[Hard], &[]), vec![1]);
+        assert_eq!(intervals(&[Good], &[]), vec![4]);
+        assert_eq!(intervals(&[Easy], &[]), vec![14]);
+    }
+
+    #[test]
+    fn matches_reference_intervals() {
+        assert_close(&intervals(&[Good; 8], &[]), &[4, 15, 49, 146, 393, 973, 2244, 4857]);
+        assert_close(&intervals(&[Easy; 4], &[]), &[14, 127, 979, 6454]);
+        assert_close(&intervals(&[Hard; 8], &[]), &[1, 2, 3, 4, 5, 6, 6, 7]);
+        assert_close(&intervals(&[Again, Good, Good, Good, Good], &[]), &[1, 2, 6, 15, 37]);
+        assert_close(&intervals(&[Good, Good, Good, Good, Again, Good, Good], &[]), &[4, 15, 49, 146, 9, 24, 61]);
+        assert_close(&intervals(&[Good, Easy, Hard, Good, Again, Easy, Good], &[]), &[4, 36, 55, 161, 9, 54, 143]);
+    }
+
+    #[test]
+    fn late_review_grows_stability_more() {
+        assert_close(&intervals(&[Good; 4], &[0, 10, 0, 0]), &[4, 15, 69, 198]);
+    }
+
+    #[test]
+    fn interval_is_capped() {
+        let long = intervals(&[Easy; 8], &[]);
+        assert_eq!(*long.last().unwrap(), MAX_INTERVAL);
+    }
+}
@@ -1,153 +1,1370 @@
-use std::io::Read;
-use std::net::TcpListener;
-use std::time::Duration;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::deck::Card;
+use crate::anki::{is_anki_export, parse_anki};
+use crate::backup::{Backup, BackupError, BackupSummary, MAX_BACKUP_BYTES, is_backup};
+use crate::crypto::{self, SEAL_OVERHEAD, Sealer, is_encrypted};
+use crate::csv::{cards_to_csv, parse_csv};
+use crate::deck::{Card, assign_ids};
+use crate::error::Error;
+use crate::json::{deck_to_json, is_json, parse_json, parse_json_decks};
+use crate::markdown::{is_markdown, parse_markdown};
+use crate::merge::MergeSummary;
+use crate::scheduler::{CardState, DeckSettings};
+use crate::storage::valid_deck_name;
 
-const MAX_IMPORT_BYTES: usize = 64 * 1024;
-const MAX_CARDS: usize = 500;
+pub const MAX_CARDS: usize = 500;
+/// Longest input line kept; a card line longer than this is skipped.
+const MAX_LINE_BYTES: usize = 4 * 1024;
+/// Most text read for one deck; lines past it are reported, not read.
+const MAX_DECK_BYTES: usize = 512 * 1024;
+/// Decks read from one transfer.
+pub const MAX_BUNDLE_DECKS: usize = 32;
+/// A JSON document can't be read a line at a time, so it's held whole up to this size.
+const MAX_JSON_BYTES: usize = 1024 * 1024;
+/// Bytes read from the connection at a time.
+const READ_CHUNK: usize = 4096;
 const LISTEN_PORT: u16 = 7878;
+/// Export listens one port above import.
+const EXPORT_PORT: u16 = 7879;
+const EXPORT_CHUNK: usize = 1024;
+const EXPORT_SETTINGS_VERSION: u8 = 3;
+/// Choices for how long export waits for a connection, cycled from the deck menu.
+pub const EXPORT_TIMEOUTS: [u32; 4] = [30, 60, 120, 300];
+/// How often the listener thread checks for cancellation while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct ImportResult {
     pub name: Option<String>,
     pub cards: Vec<Card>,
+    pub report: ParseReport,
+    /// Scheduling settings, from formats that carry them.
+    pub settings: Option<DeckSettings>,
+    /// Review state parallel to `cards`, from formats that carry it.
+    pub states: Option<Vec<CardState>>,
+}
+
+/// Why an input line didn't become a card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    MissingTab,
+    /// A CSV record with a single column.
+    MissingField,
+    EmptyFront,
+    EmptyBack,
+    /// A CSV quoted field still open at the end of the input.
+    UnterminatedQuote,
+    /// A JSON document with a syntax error on this line.
+    InvalidJson,
+    /// A JSON document whose `format` isn't this app's.
+    UnknownJsonFormat,
+    /// A JSON document with a `version` this app doesn't read.
+    UnsupportedJsonVersion,
+    /// A Markdown `Q:` line with no `A:` after it.
+    MissingAnswer,
+    /// A line over `MAX_LINE_BYTES`, dropped unread.
+    LineTooLong,
+    /// A deck name that is blank or holds control characters; the deck
+    /// keeps the default name.
+    InvalidName,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::MissingTab => write!(f, "no tab between front and back"),
+            SkipReason::MissingField => write!(f, "needs a front and a back column"),
+            SkipReason::EmptyFront => write!(f, "front is empty"),
+            SkipReason::EmptyBack => write!(f, "back is empty"),
+            SkipReason::UnterminatedQuote => write!(f, "quoted field is never closed"),
+            SkipReason::InvalidJson => write!(f, "invalid JSON"),
+            SkipReason::UnknownJsonFormat => write!(f, "JSON is not a precursor-flashcards deck"),
+            SkipReason::UnsupportedJsonVersion => write!(f, "unsupported JSON deck version"),
+            SkipReason::MissingAnswer => write!(f, "question has no A: line"),
+            SkipReason::LineTooLong => write!(f, "line is longer than {} bytes", MAX_LINE_BYTES),
+            SkipReason::InvalidName => write!(f, "deck name is blank or has control characters"),
+        }
+    }
+}
+
+/// A skipped line, numbered from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseIssue {
+    pub line: usize,
+    pub reason: SkipReason,
+}
+
+/// What the parser made of an import, sent back to the sending computer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    pub cards: usize,
+    pub issues: Vec<ParseIssue>,
+    /// Cards dropped for exceeding `MAX_CARDS`, and the line of the first one.
+    pub over_limit: usize,
+    pub first_over_limit_line: usize,
+    /// The deck's text went over `MAX_DECK_BYTES` at this line; it and the
+    /// rest of the deck weren't read.
+    pub too_large_at: Option<usize>,
+    /// Decks after this one dropped for exceeding `MAX_BUNDLE_DECKS`. Only
+    /// set on the last deck of a transfer.
+    pub decks_dropped: usize,
+}
+
+impl ParseReport {
+    /// Number of lines that didn't become cards.
+    pub fn skipped(&self) -> usize {
+        self.issues.len() + self.over_limit
+    }
+
+    /// Plain-text report, one problem per line.
+    pub fn to_text(&self) -> String {
+        let mut out = format!("Parsed {} cards, skipped {} lines\n", self.cards, self.skipped());
+        for issue in &self.issues {
+            out.push_str(&format!("line {}: {}\n", issue.line, issue.reason));
+        }
+        if self.over_limit > 0 {
+            out.push_str(&format!(
+                "warning: {} cards from line {} on were dropped (limit {} per deck)\n",
+                self.over_limit, self.first_over_limit_line, MAX_CARDS
+            ));
+        }
+        if let Some(line) = self.too_large_at {
+            out.push_str(&format!(
+                "warning: deck is over {} bytes; lines from {} on were not imported\n",
+                MAX_DECK_BYTES, line
+            ));
+        }
+        if self.decks_dropped > 0 {
+            out.push_str(&format!(
+                "warning: {} more decks were not imported (limit {} per transfer)\n",
+                self.decks_dropped, MAX_BUNDLE_DECKS
+            ));
+        }
+        out
+    }
+}
+
+/// Builds an import from parsed fields, checking each card and enforcing
+/// `MAX_CARDS`. Shared by the TSV, CSV, Anki, JSON and Markdown parsers.
+pub struct CardCollector {
+    cards: Vec<Card>,
+    report: ParseReport,
+}
+
+impl CardCollector {
+    pub fn new() -> Self {
+        Self { cards: Vec::new(), report: ParseReport::default() }
+    }
+
+    /// Add a card read from `line`, or record why it can't be one.
+    /// Fields are kept as given; one that is only whitespace counts as empty.
+    /// `id` is 0 when the input had none.
+    pub fn add(&mut self, line: usize, front: &str, back: &str, id: u64, tags: Vec<String>) {
+        if front.trim().is_empty() {
+            self.skip(line, SkipReason::EmptyFront);
+        } else if back.trim().is_empty() {
+            self.skip(line, SkipReason::EmptyBack);
+        } else if self.cards.len() >= MAX_CARDS {
+            if self.report.over_limit == 0 {
+                self.report.first_over_limit_line = line;
+            }
+            self.report.over_limit += 1;
+        } else {
+            self.cards.push(Card { id, front: front.to_string(), back: back.to_string(), tags });
+        }
+    }
+
+    pub fn skip(&mut self, line: usize, reason: SkipReason) {
+        self.report.issues.push(ParseIssue { line, reason });
+    }
+
+    pub fn finish(self, name: Option<String>) -> ImportResult {
+        let mut cards = self.cards;
+        assign_ids(&mut cards);
+        let report = ParseReport { cards: cards.len(), ..self.report };
+        ImportResult { name, cards, report, settings: None, states: None }
+    }
+}
+
+impl Default for CardCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Text formats accepted on the import port and offered for export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeckFormat {
+    Tsv = 0,
+    Csv = 1,
+    Json = 2,
+}
+
+impl DeckFormat {
+    pub fn from_u8(v: u8) -> Option<DeckFormat> {
+        match v {
+            0 => Some(DeckFormat::Tsv),
+            1 => Some(DeckFormat::Csv),
+            2 => Some(DeckFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> DeckFormat {
+        match self {
+            DeckFormat::Tsv => DeckFormat::Csv,
+            DeckFormat::Csv => DeckFormat::Json,
+            DeckFormat::Json => DeckFormat::Tsv,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DeckFormat::Tsv => "tsv",
+            DeckFormat::Csv => "csv",
+            DeckFormat::Json => "json",
+        }
+    }
+}
+
+/// Guess the format of imported text. A leading `{` means JSON; otherwise
+/// the first card line decides: a tab means TSV, a comma or leading quote CSV.
+pub fn detect_format(data: &str) -> DeckFormat {
+    if is_json(data) {
+        return DeckFormat::Json;
+    }
+    let first = data.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with('#'));
+    match first {
+        Some(line) if !line.contains('\t') && (line.contains(',') || line.starts_with('"')) => DeckFormat::Csv,
+        _ => DeckFormat::Tsv,
+    }
+}
+
+/// Parse imported text in whichever format it appears to be. Anki
+/// plain-text exports are recognised by their header lines, and Markdown
+/// notes by their headings or `Q:` lines.
+pub fn parse_import(data: &str) -> ImportResult {
+    if is_anki_export(data) {
+        return parse_anki(data);
+    }
+    if !is_json(data) && is_markdown(data) {
+        return parse_markdown(data);
+    }
+    match detect_format(data) {
+        DeckFormat::Tsv => parse_tsv(data),
+        DeckFormat::Csv => parse_csv(data),
+        DeckFormat::Json => parse_json(data),
+    }
+}
+
+/// Parse a transfer that may hold several decks: text with more than one
+/// `#name:` line, split before each one, or a JSON object with a `decks`
+/// array. Anything else is a single deck. Line numbers in each report count
+/// from the start of the whole transfer.
+pub fn parse_bundle(data: &str) -> Vec<ImportResult> {
+    if is_json(data) {
+        return parse_json_decks(data);
+    }
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for (i, line) in data.split_inclusive('\n').enumerate() {
+        if line.trim_start_matches(' ').starts_with("#name:") {
+            starts.push((i, offset));
+        }
+        offset += line.len();
+    }
+    if starts.len() <= 1 {
+        return vec![parse_import(data)];
+    }
+    // Anything before the second name belongs to the first deck
+    starts[0] = (0, 0);
+    let ends = starts.iter().skip(1).map(|&(_, end)| end).chain([data.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&(first_line, start), end)| {
+            let mut result = parse_import(&data[start..end]);
+            offset_lines(&mut result.report, first_line);
+            result
+        })
+        .collect()
+}
+
+/// Renumber a deck's report for a deck that starts `first_line` lines into
+/// the transfer.
+fn offset_lines(report: &mut ParseReport, first_line: usize) {
+    for issue in &mut report.issues {
+        issue.line += first_line;
+    }
+    if report.over_limit > 0 {
+        report.first_over_limit_line += first_line;
+    }
+}
+
+/// Report for the sender covering every deck in a transfer. `existing` is
+/// the device's deck names, to say whether each deck will be new or merged.
+pub fn bundle_report_text(results: &[ImportResult], existing: &[String]) -> String {
+    if let [result] = results {
+        return result.report.to_text();
+    }
+    let mut out = format!("Bundle of {} decks\n", results.len());
+    for (i, result) in results.iter().enumerate() {
+        let action = match &result.name {
+            _ if result.cards.is_empty() => "not saved",
+            Some(name) if existing.contains(name) => "merges into existing deck",
+            _ => "new deck",
+        };
+        let name = result.name.as_deref().unwrap_or("(unnamed)");
+        out.push_str(&format!("\n== {}: {} ({}) ==\n", i + 1, name, action));
+        out.push_str(&result.report.to_text());
+    }
+    out
+}
+
+/// What happened to one deck of a bundle on the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BundleOutcome {
+    /// Saved as a new deck with this many cards.
+    Created(usize),
+    Merged(MergeSummary),
+    /// Nothing in the section could be read as a card.
+    Empty,
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub name: String,
+    pub outcome: BundleOutcome,
+}
+
+impl std::fmt::Display for BundleEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            BundleOutcome::Created(cards) => write!(f, "{}: new, {} cards", self.name, cards),
+            BundleOutcome::Merged(s) => {
+                write!(f, "{}: +{} ~{} ={} -{}", self.name, s.added, s.updated, s.unchanged, s.removed)?;
+                if s.over_limit > 0 {
+                    write!(f, ", {} over limit", s.over_limit)?;
+                }
+                Ok(())
+            }
+            BundleOutcome::Empty => write!(f, "{}: no cards, skipped", self.name),
+            BundleOutcome::Failed(e) => write!(f, "{}: failed, {}", self.name, e),
+        }
+    }
+}
+
+/// Serialize a deck for export in the given format. Only JSON keeps the
+/// settings and review state; `states` runs parallel to `cards`.
+pub fn export_text(
+    format: DeckFormat,
+    name: &str,
+    cards: &[Card],
+    settings: &DeckSettings,
+    states: &[CardState],
+) -> String {
+    match format {
+        DeckFormat::Tsv => cards_to_tsv(name, cards),
+        DeckFormat::Csv => cards_to_csv(name, cards),
+        DeckFormat::Json => deck_to_json(name, settings, cards, states),
+    }
 }
 
 /// Parse TSV data with optional #name: header.
 /// Format:
 ///   #name:My Deck Name
 ///   front text\tback text
-///   front2\tback2
-pub fn parse_tsv(data: &str) -> Option<ImportResult> {
+///   front2\tback2\tid
+/// The optional third column is a card id written by `cards_to_tsv`, as 16
+/// hex digits. Cards without one get an id from `assign_ids`.
+/// Fields and the name may use the escapes described at `escape_field`;
+/// spaces around them are trimmed before unescaping.
+/// Every skipped line is recorded in the result's report.
+pub fn parse_tsv(data: &str) -> ImportResult {
     let mut name = None;
-    let mut cards = Vec::new();
+    let mut collector = CardCollector::new();
 
-    for line in data.lines() {
-        let line = line.trim();
-        if line.is_empty() {
+    for (i, line) in data.lines().enumerate() {
+        let line_no = i + 1;
+        // Keep tabs so "front<TAB>" reports an empty back, not a missing tab
+        let line = line.trim_matches(' ');
+        if line.trim().is_empty() {
             continue;
         }
         if let Some(n) = line.strip_prefix("#name:") {
-            name = Some(n.trim().to_string());
+            let n = unescape_field(n.trim_matches(' '));
+            if valid_deck_name(&n) {
+                name = Some(n);
+            } else {
+                collector.skip(line_no, SkipReason::InvalidName);
+            }
             continue;
         }
         // Skip comment lines
         if line.starts_with('#') {
             continue;
         }
-        if let Some((front, back)) = line.split_once('\t') {
-            let front = front.trim();
-            let back = back.trim();
-            if !front.is_empty() && !back.is_empty() {
-                if cards.len() >= MAX_CARDS {
-                    break;
-                }
-                cards.push(Card {
-                    front: front.to_string(),
-                    back: back.to_string(),
-                });
+        match line.split_once('\t') {
+            Some((front, rest)) => {
+                let (back, id) = split_id_column(rest);
+                let front = unescape_field(front.trim_matches(' '));
+                let back = unescape_field(back.trim_matches(' '));
+                collector.add(line_no, &front, &back, id, Vec::new());
             }
+            None => collector.skip(line_no, SkipReason::MissingTab),
+        }
+    }
+    collector.finish(name)
+}
+
+/// Escape a TSV field so it fits on one line and reads back exactly:
+///   `\\` backslash, `\t` tab, `\n` newline, `\r` carriage return,
+///   `\s` a space at the start or end of the field (which would be trimmed),
+///   `\#` a `#` at the start (which would make the line a comment).
+/// Other characters, including spaces inside the field, are written as is.
+pub fn escape_field(field: &str) -> String {
+    let leading = field.len() - field.trim_start_matches(' ').len();
+    let trailing_start = field.trim_end_matches(' ').len().max(leading);
+    let mut out = String::with_capacity(field.len());
+    for (i, c) in field.char_indices() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            ' ' if i < leading || i >= trailing_start => out.push_str("\\s"),
+            '#' if i == 0 => out.push_str("\\#"),
+            c => out.push(c),
         }
     }
+    out
+}
+
+/// Reverse `escape_field`. A backslash before any other character, or at
+/// the end, is kept literally.
+pub fn unescape_field(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('s') => out.push(' '),
+            Some('#') => out.push('#'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Fail a transfer in which no deck produced any cards. A single deck fails
+/// with its first skip reason, as before bundles existed.
+fn into_bundle_result(mut results: Vec<ImportResult>) -> ImportOutcome {
+    if results.len() == 1 {
+        return into_import_result(results.remove(0)).map(|result| vec![result]);
+    }
+    if results.iter().all(|r| r.cards.is_empty()) {
+        return Err(Error::Parse(format!("no cards found in any of {} decks", results.len())));
+    }
+    Ok(results)
+}
+
+/// Fail an import that produced no cards, with the first reason if there is one.
+fn into_import_result(result: ImportResult) -> Result<ImportResult, Error> {
+    if !result.cards.is_empty() {
+        return Ok(result);
+    }
+    match result.report.issues.first() {
+        Some(issue) => Err(Error::Parse(format!("no cards found (line {}: {})", issue.line, issue.reason))),
+        None => Err(Error::Parse("no cards found".to_string())),
+    }
+}
+
+/// Split a trailing id column off the back text. Anything that isn't exactly
+/// 16 hex digits is left as part of the back, as older files had no id column.
+fn split_id_column(rest: &str) -> (&str, u64) {
+    if let Some((back, id)) = rest.rsplit_once('\t') {
+        if let Some(id) = parse_card_id(id) {
+            return (back, id);
+        }
+    }
+    (rest, 0)
+}
+
+/// A card id column: exactly 16 hex digits.
+pub fn parse_card_id(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if text.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// Every deck parsed from one import connection, or why it failed.
+pub type ImportOutcome = Result<Vec<ImportResult>, Error>;
+
+/// What arrived on the import port.
+pub enum Transfer {
+    Decks(Vec<ImportResult>),
+    /// An encrypted export, held until its passphrase is entered on the device.
+    Encrypted(Vec<u8>),
+}
+
+/// Decrypt an encrypted export and parse the decks inside it, with the same
+/// limits as a transfer sent in the clear.
+pub fn open_encrypted(data: &[u8], passphrase: &str) -> ImportOutcome {
+    let plain = crypto::open(data, passphrase)?;
+    into_bundle_result(read_transfer(Cursor::new(plain))?)
+}
+
+/// A finished decryption, with the encrypted data handed back so a wrong
+/// passphrase can be retried.
+pub type UnlockOutcome = (ImportOutcome, Vec<u8>);
 
-    if cards.is_empty() {
-        None
+/// Decryption of a received export on its own thread, since the key
+/// derivation takes seconds on the device. `done_op` is sent to `cid` when
+/// it finishes.
+pub struct UnlockJob {
+    result: Arc<Mutex<Option<UnlockOutcome>>>,
+}
+
+impl UnlockJob {
+    pub fn start(data: Vec<u8>, passphrase: String, cid: xous::CID, done_op: usize) -> Self {
+        let result = Arc::new(Mutex::new(None));
+        let thread_result = result.clone();
+        std::thread::spawn(move || {
+            let outcome = open_encrypted(&data, &passphrase);
+            *thread_result.lock().unwrap() = Some((outcome, data));
+            xous::send_message(cid, xous::Message::new_scalar(done_op, 0, 0, 0, 0)).ok();
+        });
+        Self { result }
+    }
+
+    pub fn take_result(&self) -> Option<UnlockOutcome> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+/// A backup received for restore, with what it holds.
+pub type RestoreOutcome = Result<(Backup, BackupSummary), Error>;
+
+/// TCP import listener running on its own thread, so the UI stays live
+/// while waiting for a connection. It reads either decks to import or, for
+/// a restore, a backup archive.
+///
+/// Each listener has a one-time pairing code from the TRNG, shown on the
+/// device. The sender's first line must be `#code:<code>`; a connection
+/// without it is refused before anything is parsed, and the listener stops.
+/// If no code can be drawn the listener doesn't start.
+pub struct ImportListener<T = Transfer> {
+    cancel: Arc<AtomicBool>,
+    result: Arc<Mutex<Option<Result<T, Error>>>>,
+    code: String,
+}
+
+impl ImportListener {
+    /// Start listening for a single connection on port 7878. When data has
+    /// been received and parsed (or the listener fails), `done_op` is sent to
+    /// `cid` and the outcome, one result per deck sent or an encrypted
+    /// payload, can be collected with `take_result`. `existing` names the
+    /// decks already on the device, for the report sent back.
+    pub fn start(trng: &trng::Trng, cid: xous::CID, done_op: usize, existing: Vec<String>) -> Result<Self, Error> {
+        Self::spawn(trng, cid, done_op, move |stream| read_import(stream, &existing))
+    }
+}
+
+impl ImportListener<(Backup, BackupSummary)> {
+    /// Start listening for a backup archive on port 7878. The outcome is a
+    /// backup that has passed its checks; nothing is restored yet.
+    pub fn start_restore(trng: &trng::Trng, cid: xous::CID, done_op: usize) -> Result<Self, Error> {
+        Self::spawn(trng, cid, done_op, read_restore)
+    }
+}
+
+impl<T: Send + 'static> ImportListener<T> {
+    fn spawn(
+        trng: &trng::Trng,
+        cid: xous::CID,
+        done_op: usize,
+        read: impl FnOnce(&mut BufReader<TcpStream>) -> Result<T, Error> + Send + 'static,
+    ) -> Result<Self, Error> {
+        let code = pairing_code(trng)?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let result = Arc::new(Mutex::new(None));
+        let (thread_cancel, thread_result, thread_code) = (cancel.clone(), result.clone(), code.clone());
+        std::thread::spawn(move || {
+            let outcome = match accept_import(&thread_cancel) {
+                Ok(Some(stream)) => accept_pairing(stream, &thread_code).and_then(|mut reader| read(&mut reader)),
+                Ok(None) => return, // cancelled; nobody is waiting
+                Err(e) => Err(e),
+            };
+            *thread_result.lock().unwrap() = Some(outcome);
+            xous::send_message(cid, xous::Message::new_scalar(done_op, 0, 0, 0, 0)).ok();
+        });
+        Ok(Self { cancel, result, code })
+    }
+
+    /// The code the sender must present, for the waiting screen.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Stop waiting for a connection. A connection already accepted is still
+    /// read and `done_op` still sent, but the result stays with this listener.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    pub fn take_result(&self) -> Option<Result<T, Error>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+/// Wait for one connection, polling so cancellation is noticed.
+/// Returns None if cancelled first.
+fn accept_import(cancel: &AtomicBool) -> Result<Option<TcpStream>, Error> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", LISTEN_PORT)).map_err(Error::Network)?;
+    listener.set_nonblocking(true).map_err(Error::Network)?;
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                log::info!("Import connection from {:?}", addr);
+                stream.set_nonblocking(false).map_err(Error::Network)?;
+                return Ok(Some(stream));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(Error::Network(e)),
+        }
+    }
+}
+
+/// A six-digit code from the TRNG. There is no fallback: a predictable code
+/// would let anyone on the network pair.
+fn pairing_code(trng: &trng::Trng) -> Result<String, Error> {
+    let random = trng.get_u32().map_err(|_| Error::Random)?;
+    Ok(format!("{:06}", random % 1_000_000))
+}
+
+/// Check the pairing line at the start of an accepted connection, refusing
+/// the sender if it's missing or wrong. Returns the connection, buffered,
+/// positioned after that line.
+fn accept_pairing(stream: TcpStream, code: &str) -> Result<BufReader<TcpStream>, Error> {
+    stream.set_read_timeout(Some(Duration::from_secs(10))).ok();
+    let sender = stream.peer_addr().map_or("unknown sender".to_string(), |addr| addr.ip().to_string());
+    let mut reader = BufReader::with_capacity(READ_CHUNK, stream);
+    if read_pairing(&mut reader, code) {
+        return Ok(reader);
+    }
+    log::warn!("Rejected import from {}: wrong pairing code", sender);
+    reader.get_mut().write_all(b"Refused: missing or wrong pairing code\n").ok();
+    Err(Error::Pairing(sender))
+}
+
+/// Read the first line of a transfer and check it is `#code:<code>`.
+pub fn read_pairing(reader: &mut impl BufRead, code: &str) -> bool {
+    let mut line = Vec::new();
+    match read_line(reader, &mut line) {
+        Ok(Some(false)) => {
+            let line = String::from_utf8_lossy(&line);
+            line.trim().strip_prefix("#code:").map(str::trim) == Some(code)
+        }
+        _ => false,
+    }
+}
+
+/// Read TSV, CSV, JSON or Markdown data from an accepted connection and parse
+/// it into one or more decks, writing the parse reports back so the sender
+/// can see which lines were skipped and which limits were hit. An encrypted
+/// export is read whole and left for the device to decrypt. A failed read
+/// refuses the whole transfer rather than importing part of a deck.
+fn read_import(reader: &mut BufReader<TcpStream>, existing: &[String]) -> Result<Transfer, Error> {
+    let mut head = Vec::new();
+    Read::by_ref(reader).take(4).read_to_end(&mut head).map_err(Error::Network)?;
+    if is_encrypted(&head) {
+        return read_encrypted(head, reader).map(Transfer::Encrypted);
+    }
+    // The sender may already have closed its end; replies are best effort
+    match read_transfer(Cursor::new(head).chain(&mut *reader)) {
+        Ok(results) => {
+            reader.get_mut().write_all(bundle_report_text(&results, existing).as_bytes()).ok();
+            into_bundle_result(results).map(Transfer::Decks)
+        }
+        Err(Error::Parse(msg)) => {
+            reader.get_mut().write_all(format!("Import refused: {}\n", msg).as_bytes()).ok();
+            Err(Error::Parse(msg))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the rest of an encrypted export, which is held whole like JSON.
+fn read_encrypted(mut data: Vec<u8>, reader: &mut BufReader<TcpStream>) -> Result<Vec<u8>, Error> {
+    Read::by_ref(reader).take(MAX_JSON_BYTES as u64 + 1).read_to_end(&mut data).map_err(Error::Network)?;
+    let reply = if data.len() > MAX_JSON_BYTES {
+        format!("Import refused: encrypted file is larger than {} bytes\n", MAX_JSON_BYTES)
     } else {
-        Some(ImportResult { name, cards })
+        format!("Encrypted file received ({} bytes)\nEnter the passphrase on the device\n", data.len())
+    };
+    reader.get_mut().write_all(reply.as_bytes()).ok();
+    if data.len() > MAX_JSON_BYTES {
+        return Err(Error::Parse(format!("encrypted file is larger than {} bytes", MAX_JSON_BYTES)));
     }
+    Ok(data)
 }
 
-/// Listen for a single TCP connection on port 7878, read TSV data.
-/// Returns None if cancelled or on error.
-pub fn listen_for_import() -> Option<ImportResult> {
-    let listener = match TcpListener::bind(format!("0.0.0.0:{}", LISTEN_PORT)) {
-        Ok(l) => l,
-        Err(e) => {
-            log::error!("Failed to bind port {}: {:?}", LISTEN_PORT, e);
-            return None;
+/// Read a whole transfer and parse it into decks, one deck at a time, so
+/// memory stays bounded however much is sent. Text is read line by line and
+/// split before each `#name:` line as in `parse_bundle`; each deck is parsed
+/// as soon as the next one starts. A JSON document is read whole, up to
+/// `MAX_JSON_BYTES`. Limits on lines, decks and deck count are recorded in
+/// the reports rather than silently cutting the input.
+/// A read error, such as the sender stalling, fails the whole transfer.
+pub fn read_transfer(mut reader: impl BufRead) -> Result<Vec<ImportResult>, Error> {
+    // Skip leading whitespace to see whether this is JSON, counting lines
+    // so reports still number from the top of the transfer
+    let mut blank_lines = 0;
+    let first = loop {
+        let buf = reader.fill_buf().map_err(Error::Network)?;
+        if buf.is_empty() {
+            break None;
+        }
+        let skip = buf.iter().position(|b| !b.is_ascii_whitespace());
+        let n = skip.unwrap_or(buf.len());
+        blank_lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+        let first = skip.map(|i| buf[i]);
+        reader.consume(n);
+        if first.is_some() {
+            break first;
         }
     };
+    match first {
+        None => Err(Error::Parse("no data received".to_string())),
+        Some(b'{') => read_json_transfer(reader, blank_lines),
+        Some(_) => read_text_transfer(reader, blank_lines),
+    }
+}
 
-    // Set a timeout so we can check for cancellation
-    listener.set_nonblocking(false).ok();
-
-    // Accept one connection
-    match listener.accept() {
-        Ok((mut stream, addr)) => {
-            log::info!("Import connection from {:?}", addr);
-            stream.set_read_timeout(Some(Duration::from_secs(10))).ok();
-
-            let mut buf = vec![0u8; MAX_IMPORT_BYTES];
-            let mut total = 0;
-            loop {
-                match stream.read(&mut buf[total..]) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        total += n;
-                        if total >= MAX_IMPORT_BYTES {
-                            break;
-                        }
-                    }
-                    Err(_) => break,
-                }
-            }
+fn read_json_transfer(reader: impl Read, blank_lines: usize) -> Result<Vec<ImportResult>, Error> {
+    let mut data = Vec::new();
+    reader.take(MAX_JSON_BYTES as u64 + 1).read_to_end(&mut data).map_err(Error::Network)?;
+    if blank_lines + data.len() > MAX_JSON_BYTES {
+        return Err(Error::Parse(format!(
+            "JSON is larger than {} bytes; send its decks as separate files",
+            MAX_JSON_BYTES
+        )));
+    }
+    let text = "\n".repeat(blank_lines) + &String::from_utf8_lossy(&data);
+    Ok(parse_bundle(&text))
+}
+
+fn read_text_transfer(mut reader: impl BufRead, blank_lines: usize) -> Result<Vec<ImportResult>, Error> {
+    let mut results = Vec::new();
+    let mut section = Some(Section::new(blank_lines));
+    let mut decks_dropped = 0;
+    let mut line = Vec::new();
+    let mut line_no = blank_lines;
 
-            if total == 0 {
-                return None;
+    while let Some(too_long) = read_line(&mut reader, &mut line).map_err(Error::Network)? {
+        line_no += 1;
+        if line_no == blank_lines + 1 && is_backup(&line) {
+            return Err(Error::Parse("this is a full backup; use Restore from the deck list menu".to_string()));
+        }
+        let text = String::from_utf8_lossy(&line);
+        let is_name = !too_long && text.trim_start_matches(' ').starts_with("#name:");
+        if is_name && section.as_ref().is_none_or(|s| s.named) {
+            results.extend(section.take().map(Section::finish));
+            if results.len() < MAX_BUNDLE_DECKS {
+                section = Some(Section::new(line_no - 1));
+            } else {
+                decks_dropped += 1;
             }
+        }
+        if let Some(section) = &mut section {
+            section.named |= is_name;
+            section.push(line_no, &text, too_long);
+        }
+    }
+    results.extend(section.map(Section::finish));
+    if let Some(last) = results.last_mut() {
+        last.report.decks_dropped = decks_dropped;
+    }
+    Ok(results)
+}
+
+/// Read one line into `line`, without its newline, keeping at most
+/// `MAX_LINE_BYTES` of it. Returns whether the line was longer than that,
+/// or None once the input has ended.
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> std::io::Result<Option<bool>> {
+    line.clear();
+    let mut too_long = false;
+    let mut read_any = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(read_any.then_some(too_long));
+        }
+        read_any = true;
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let chunk = &buf[..newline.unwrap_or(buf.len())];
+        let room = MAX_LINE_BYTES.saturating_sub(line.len());
+        too_long |= chunk.len() > room;
+        line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        let used = newline.map_or(buf.len(), |i| i + 1);
+        reader.consume(used);
+        if newline.is_some() {
+            return Ok(Some(too_long));
+        }
+    }
+}
 
-            let text = String::from_utf8_lossy(&buf[..total]);
-            parse_tsv(&text)
+/// The text of one deck while a transfer is read.
+struct Section {
+    text: String,
+    /// Lines of the transfer before this deck's first line.
+    first_line: usize,
+    /// A `#name:` line has been seen; the next one starts another deck.
+    named: bool,
+    too_long: Vec<ParseIssue>,
+    too_large_at: Option<usize>,
+}
+
+impl Section {
+    fn new(first_line: usize) -> Self {
+        Self { text: String::new(), first_line, named: false, too_long: Vec::new(), too_large_at: None }
+    }
+
+    /// Add line `line_no` of the transfer. A line that was too long is kept
+    /// as a blank one, so the parser's line numbers stay right.
+    fn push(&mut self, line_no: usize, text: &str, too_long: bool) {
+        if self.too_large_at.is_some() {
+            return;
+        }
+        let text = if too_long { "" } else { text };
+        if self.text.len() + text.len() + 1 > MAX_DECK_BYTES {
+            self.too_large_at = Some(line_no);
+            return;
         }
-        Err(e) => {
-            log::error!("Accept failed: {:?}", e);
-            None
+        if too_long {
+            self.too_long.push(ParseIssue { line: line_no, reason: SkipReason::LineTooLong });
         }
+        self.text.push_str(text);
+        self.text.push('\n');
+    }
+
+    fn finish(self) -> ImportResult {
+        let mut result = parse_import(&self.text);
+        offset_lines(&mut result.report, self.first_line);
+        result.report.issues.extend(self.too_long);
+        result.report.issues.sort_by_key(|issue| issue.line);
+        result.report.too_large_at = self.too_large_at;
+        result
     }
 }
 
+/// Read a backup archive from an accepted connection and check it, telling
+/// the sender whether it can be restored. Nothing is written here; the user
+/// confirms on the device first.
+fn read_restore(reader: &mut BufReader<TcpStream>) -> RestoreOutcome {
+    // A transfer cut short fails the checksum, so read errors just end it
+    let mut data = Vec::new();
+    Read::by_ref(reader).take(MAX_BACKUP_BYTES as u64 + 1).read_to_end(&mut data).ok();
+    if data.is_empty() {
+        return Err(Error::Parse("no data received".to_string()));
+    }
+
+    let checked = if data.len() > MAX_BACKUP_BYTES {
+        Err(BackupError::TooLarge)
+    } else {
+        Backup::decode(&data).and_then(|backup| backup.verify().map(|summary| (backup, summary)))
+    };
+    let reply = match &checked {
+        Ok((_, s)) => format!(
+            "Backup verified: {} decks, {} cards, {} reviews\nConfirm the restore on the device\n",
+            s.decks, s.cards, s.reviews
+        ),
+        Err(e) => format!("Restore refused: {}\n", e),
+    };
+    reader.get_mut().write_all(reply.as_bytes()).ok();
+    checked.map_err(Error::from)
+}
+
 pub fn listen_port() -> u16 {
     LISTEN_PORT
 }
 
-/// Export cards to TSV format.
+/// Export cards to TSV format, with each card's id in a third column so
+/// a later import can match cards to their review state. Fields are
+/// escaped, so `parse_tsv` reads back exactly the same cards.
 pub fn cards_to_tsv(name: &str, cards: &[Card]) -> String {
-    let mut output = format!("#name:{}\n", name);
+    let mut output = format!("#name:{}\n", escape_field(name));
     for card in cards {
-        output.push_str(&card.front);
+        output.push_str(&escape_field(&card.front));
         output.push('\t');
-        output.push_str(&card.back);
-        output.push('\n');
+        output.push_str(&escape_field(&card.back));
+        output.push_str(&format!("\t{:016x}\n", card.id));
     }
     output
 }
 
-/// Export deck via TCP on port 7879 (one port above import).
-/// Waits for a client to connect and sends the TSV data.
-pub fn export_via_tcp(name: &str, cards: &[Card]) -> Result<usize, &'static str> {
-    use std::io::Write;
+pub fn export_port() -> u16 {
+    EXPORT_PORT
+}
 
-    const EXPORT_PORT: u16 = 7879;
+/// Export preferences, kept across sessions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportSettings {
+    /// Seconds to wait for the computer to connect before giving up.
+    pub timeout_secs: u32,
+    pub format: DeckFormat,
+    /// Seal exports with a passphrase entered on each export.
+    pub encrypt: bool,
+}
+
+impl ExportSettings {
+    pub fn new() -> Self {
+        Self { timeout_secs: 60, format: DeckFormat::Tsv, encrypt: false }
+    }
 
-    let listener = match TcpListener::bind(format!("0.0.0.0:{}", EXPORT_PORT)) {
-        Ok(l) => l,
-        Err(_) => return Err("Failed to bind export port"),
+    /// Step to the next choice in `EXPORT_TIMEOUTS`.
+    pub fn cycle_timeout(&mut self) {
+        let pos = EXPORT_TIMEOUTS.iter().position(|&t| t == self.timeout_secs);
+        self.timeout_secs = EXPORT_TIMEOUTS[pos.map(|p| (p + 1) % EXPORT_TIMEOUTS.len()).unwrap_or(0)];
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serialize export settings:
+/// [u8: version][u32: timeout_secs][u8: format][u8: flags, bit 0 = encrypt]
+pub fn serialize_export_settings(settings: &ExportSettings) -> Vec<u8> {
+    let mut buf = vec![EXPORT_SETTINGS_VERSION];
+    buf.extend_from_slice(&settings.timeout_secs.to_le_bytes());
+    buf.push(settings.format as u8);
+    buf.push(settings.encrypt as u8);
+    buf
+}
+
+/// Deserialize export settings. Version 1 had no format and exported TSV;
+/// version 2 had no flags and never encrypted.
+pub fn deserialize_export_settings(data: &[u8]) -> Option<ExportSettings> {
+    if data.len() < 5 {
+        return None;
+    }
+    let timeout_secs = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+    let (format, encrypt) = match data[0] {
+        1 => (DeckFormat::Tsv, false),
+        2 => (DeckFormat::from_u8(*data.get(5)?)?, false),
+        EXPORT_SETTINGS_VERSION => (DeckFormat::from_u8(*data.get(5)?)?, *data.get(6)? & 1 != 0),
+        _ => return None,
     };
+    Some(ExportSettings { timeout_secs, format, encrypt })
+}
 
-    log::info!("Waiting for export connection on port {}", EXPORT_PORT);
+/// Where a running export is up to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportProgress {
+    /// Deriving the key and sealing the payload, before listening.
+    Encrypting,
+    /// Waiting for the computer to connect.
+    Waiting { remaining_secs: u32 },
+    /// Connected; bytes written so far.
+    Sending { sent: usize },
+}
 
-    match listener.accept() {
-        Ok((mut stream, addr)) => {
-            log::info!("Export connection from {:?}", addr);
-            let tsv = cards_to_tsv(name, cards);
-            match stream.write_all(tsv.as_bytes()) {
-                Ok(_) => {
-                    log::info!("Exported {} bytes", tsv.len());
-                    Ok(tsv.len())
+/// TCP export running on its own thread. With a `sealer` the payload is
+/// encrypted there first, so the key derivation doesn't stall the UI.
+/// `update_op` is sent to the app whenever the stage, countdown or byte
+/// count changes and once more when the export finishes, after which
+/// `take_result` returns the outcome.
+pub struct ExportJob {
+    cancel: Arc<AtomicBool>,
+    progress: Arc<Mutex<ExportProgress>>,
+    result: Arc<Mutex<Option<Result<usize, Error>>>>,
+    total: usize,
+}
+
+impl ExportJob {
+    pub fn start(
+        payload: Vec<u8>,
+        sealer: Option<Sealer>,
+        timeout: Duration,
+        cid: xous::CID,
+        update_op: usize,
+    ) -> Self {
+        let total = payload.len() + if sealer.is_some() { SEAL_OVERHEAD } else { 0 };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let first = match sealer {
+            Some(_) => ExportProgress::Encrypting,
+            None => ExportProgress::Waiting { remaining_secs: timeout.as_secs() as u32 },
+        };
+        let progress = Arc::new(Mutex::new(first));
+        let result = Arc::new(Mutex::new(None));
+        let (thread_cancel, thread_progress, thread_result) = (cancel.clone(), progress.clone(), result.clone());
+        std::thread::spawn(move || {
+            let notify = || {
+                xous::send_message(cid, xous::Message::new_scalar(update_op, 0, 0, 0, 0)).ok();
+            };
+            let report = |p: ExportProgress| {
+                *thread_progress.lock().unwrap() = p;
+                notify();
+            };
+            let payload = match sealer {
+                Some(sealer) => {
+                    let sealed = sealer.seal(&payload);
+                    if thread_cancel.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    report(ExportProgress::Waiting { remaining_secs: timeout.as_secs() as u32 });
+                    sealed
                 }
-                Err(_) => Err("Write failed"),
+                None => payload,
+            };
+            let outcome = match accept_export(timeout, &thread_cancel, &report) {
+                Ok(Some(mut stream)) => send_export(&mut stream, &payload, &thread_cancel, &report),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+            *thread_result.lock().unwrap() = Some(outcome);
+            notify();
+        });
+        Self { cancel, progress, result, total }
+    }
+
+    /// Abandon the export. No result will be reported.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    pub fn progress(&self) -> ExportProgress {
+        *self.progress.lock().unwrap()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total
+    }
+
+    pub fn take_result(&self) -> Option<Result<usize, Error>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+/// Wait up to `timeout` for a connection on the export port, reporting the
+/// countdown once a second. Returns None if cancelled first.
+fn accept_export(
+    timeout: Duration,
+    cancel: &AtomicBool,
+    report: &dyn Fn(ExportProgress),
+) -> Result<Option<TcpStream>, Error> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", EXPORT_PORT)).map_err(Error::Network)?;
+    listener.set_nonblocking(true).map_err(Error::Network)?;
+    log::info!("Waiting for export connection on port {}", EXPORT_PORT);
+
+    let started = Instant::now();
+    let mut last_remaining = timeout.as_secs() as u32;
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            return Err(Error::Network(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "no connection before timeout",
+            )));
+        }
+        let remaining = (timeout - elapsed).as_secs() as u32 + 1;
+        if remaining != last_remaining {
+            last_remaining = remaining;
+            report(ExportProgress::Waiting { remaining_secs: remaining });
+        }
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                log::info!("Export connection from {:?}", addr);
+                stream.set_nonblocking(false).map_err(Error::Network)?;
+                return Ok(Some(stream));
             }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(Error::Network(e)),
         }
-        Err(_) => Err("Accept failed"),
+    }
+}
+
+/// Write the payload in chunks, reporting progress and honouring cancellation.
+fn send_export(
+    stream: &mut TcpStream,
+    payload: &[u8],
+    cancel: &AtomicBool,
+    report: &dyn Fn(ExportProgress),
+) -> Result<usize, Error> {
+    stream.set_write_timeout(Some(Duration::from_secs(10))).ok();
+    let mut sent = 0;
+    report(ExportProgress::Sending { sent });
+    for chunk in payload.chunks(EXPORT_CHUNK) {
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(sent);
+        }
+        stream.write_all(chunk).map_err(Error::Network)?;
+        sent += chunk.len();
+        report(ExportProgress::Sending { sent });
+    }
+    log::info!("Exported {} bytes", sent);
+    Ok(sent)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Small deterministic generator so the property tests need no extra
+    /// crates and fail reproducibly. Shared with the other formats' tests.
+    pub(crate) struct XorShift(pub u64);
+
+    impl XorShift {
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        pub(crate) fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Characters weighted towards the ones the escaping has to handle.
+    const ALPHABET: &[char] = &[
+        'a', 'b', 'n', 't', 's', 'r', '0', ' ', ' ', '\t', '\n', '\r', '\\', '\\', '#', ',', '"', 'é', '漢',
+        '\u{a0}',
+    ];
+
+    fn random_text(rng: &mut XorShift, max_len: usize) -> String {
+        let len = rng.below(max_len + 1);
+        (0..len).map(|_| ALPHABET[rng.below(ALPHABET.len())]).collect()
+    }
+
+    /// Random text with at least one non-whitespace character, since a card
+    /// side that is only whitespace is rejected as empty.
+    pub(crate) fn random_field(rng: &mut XorShift) -> String {
+        loop {
+            let text = random_text(rng, 12);
+            if !text.trim().is_empty() {
+                return text;
+            }
+        }
+    }
+
+    /// A random name the deck index can hold.
+    pub(crate) fn random_name(rng: &mut XorShift) -> String {
+        random_field(rng).replace(char::is_control, "_")
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..5000 {
+            let text = random_text(&mut rng, 16);
+            let escaped = escape_field(&text);
+            assert!(!escaped.contains(['\t', '\n', '\r']), "{:?} -> {:?}", text, escaped);
+            assert!(!escaped.starts_with([' ', '#']) && !escaped.ends_with(' '), "{:?} -> {:?}", text, escaped);
+            assert_eq!(unescape_field(&escaped), text);
+        }
+    }
+
+    #[test]
+    fn tsv_round_trips() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let name = random_name(&mut rng);
+            let mut cards: Vec<Card> = (0..1 + rng.below(20))
+                .map(|_| Card {
+                    id: rng.next() | 1,
+                    front: random_field(&mut rng),
+                    back: random_field(&mut rng),
+                    tags: Vec::new(),
+                })
+                .collect();
+            assign_ids(&mut cards);
+
+            let tsv = cards_to_tsv(&name, &cards);
+            assert_eq!(tsv.lines().count(), cards.len() + 1);
+            let result = parse_tsv(&tsv);
+            assert_eq!(result.report.issues, vec![], "{:?}", tsv);
+            assert_eq!(result.name.as_deref(), Some(name.as_str()));
+            assert_eq!(result.cards.len(), cards.len());
+            for (parsed, card) in result.cards.iter().zip(&cards) {
+                assert_eq!((parsed.id, &parsed.front, &parsed.back), (card.id, &card.front, &card.back));
+            }
+        }
+    }
+
+    #[test]
+    fn plain_files_still_parse() {
+        let result = parse_tsv("#name: Paths \n  C:\\dir\\x\tback\\\nfront2\t a b \n# comment\n");
+        assert_eq!(result.name.as_deref(), Some("Paths"));
+        assert_eq!(result.cards[0].front, "C:\\dir\\x");
+        assert_eq!(result.cards[0].back, "back\\");
+        assert_eq!(result.cards[1].back, "a b");
+    }
+
+    #[test]
+    fn names_that_would_split_the_index_are_dropped() {
+        for data in ["#name:a\\nb\nx\ty\n", "#name:a\\tb\nx\ty\n", "#name:  \nx\ty\n"] {
+            let results = read_transfer(data.as_bytes()).unwrap();
+            assert_eq!(results[0].name, None, "{:?}", data);
+            assert_eq!(results[0].report.issues, vec![ParseIssue { line: 1, reason: SkipReason::InvalidName }]);
+            assert_eq!(results[0].cards.len(), 1);
+        }
+        assert!(valid_deck_name("Español 漢字"));
+        assert!(!valid_deck_name("a\rb"));
+    }
+
+    #[test]
+    fn multi_line_fields_stay_on_one_line() {
+        let cards = vec![Card { id: 7, front: "#1\nhit".into(), back: " two\tcolumns ".into(), tags: Vec::new() }];
+        let tsv = cards_to_tsv("Deck", &cards);
+        assert_eq!(tsv, "#name:Deck\n\\#1\\nhit\t\\stwo\\tcolumns\\s\t0000000000000007\n");
+    }
+
+    #[test]
+    fn bundles_split_into_decks() {
+        let data = "# semester set\n#name:One\na\tb\n\n#name:Two\nc,d\ne\n#name:Three\n# nothing here\n";
+        let results = parse_bundle(data);
+        let names: Vec<_> = results.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("One"), Some("Two"), Some("Three")]);
+        assert_eq!(results[1].cards[0].back, "d");
+        assert_eq!(results[1].report.issues, vec![ParseIssue { line: 7, reason: SkipReason::MissingField }]);
+        assert!(results[2].cards.is_empty());
+
+        let report = bundle_report_text(&results, &["Two".to_string()]);
+        assert!(report.starts_with("Bundle of 3 decks\n\n== 1: One (new deck) ==\nParsed 1 cards"), "{}", report);
+        assert!(report.contains("== 2: Two (merges into existing deck) ==\nParsed 1 cards, skipped 1 lines\nline 7:"));
+        assert!(report.contains("== 3: Three (not saved) =="));
+        assert_eq!(into_bundle_result(results).unwrap().len(), 3);
+
+        let json = "{ \"decks\": [ { \"name\": \"A\", \"cards\": [ { \"front\": \"x\", \"back\": \"y\" } ] }, \
+                    { \"name\": \"B\", \"cards\": [] } ] }";
+        let names: Vec<_> = parse_bundle(json).into_iter().map(|r| r.name).collect();
+        assert_eq!(names, vec![Some("A".to_string()), Some("B".to_string())]);
+        assert_eq!(parse_bundle("#name:Solo\na\tb\n").len(), 1);
+
+        // As tools/apkg2txt.py sends a multi-deck package
+        let anki = "#name:Lang::A\n#separator:tab\n#html:true\n#deck:Lang::A\n#guid column:1\n#tags column:4\n\
+                    g1\thola\thello\tverb\n\
+                    #name:Lang::B\n#separator:tab\n#html:true\n#deck:Lang::B\n#guid column:1\n#tags column:4\n\
+                    g2\t\"a\tb\"\tc\t\n";
+        let results = parse_bundle(anki);
+        let decks: Vec<_> = results.iter().map(|r| (r.name.as_deref(), r.cards.len())).collect();
+        assert_eq!(decks, vec![(Some("Lang::A"), 1), (Some("Lang::B"), 1)]);
+        assert_eq!(results[0].cards[0].tags, vec!["verb".to_string()]);
+        assert_eq!(results[1].cards[0].front, "a\tb");
+    }
+
+    #[test]
+    fn large_transfers_stream_with_limits() {
+        // 600 cards is far past the old 64 KiB buffer and the card limit
+        let mut data = String::from("\n\n#name:Big\n");
+        for i in 0..600 {
+            data.push_str(&format!("front {:03} {}\tback\n", i, "x".repeat(150)));
+            if i == 10 {
+                data.push_str(&format!("{}\tback\n", "y".repeat(MAX_LINE_BYTES)));
+            }
+        }
+        let results = read_transfer(data.as_bytes()).unwrap();
+        let report = &results[0].report;
+        assert_eq!(results[0].name.as_deref(), Some("Big"));
+        assert_eq!((report.cards, report.over_limit, report.first_over_limit_line), (500, 100, 505));
+        assert_eq!(report.issues, vec![ParseIssue { line: 15, reason: SkipReason::LineTooLong }]);
+        assert_eq!(results[0].cards[11].front, format!("front 011 {}", "x".repeat(150)));
+
+        let huge = format!("#name:Huge\n{}", format!("{}\tback\n", "z".repeat(4000)).repeat(200));
+        let results = read_transfer(huge.as_bytes()).unwrap();
+        assert_eq!(results[0].report.too_large_at, Some(2 + MAX_DECK_BYTES / 4006));
+        assert!(results[0].report.to_text().contains("lines from 132 on were not imported"));
+
+        let many: String = (0..40).map(|i| format!("#name:D{}\na\tb\n", i)).collect();
+        let results = read_transfer(many.as_bytes()).unwrap();
+        assert_eq!(results.len(), MAX_BUNDLE_DECKS);
+        assert_eq!(results[MAX_BUNDLE_DECKS - 1].report.decks_dropped, 8);
+        assert!(results[1].report.issues.is_empty());
+
+        let json = "\n{ \"name\": \"J\",\n \"cards\": [1.5] }";
+        assert_eq!(read_transfer(json.as_bytes()).unwrap()[0].report.issues[0].line, 3);
+        assert!(read_transfer(&b"FCBK\x01\0\0\0"[..]).is_err());
+        assert!(read_transfer(&b" \n "[..]).is_err());
+    }
+
+    /// A connection that stalls after sending part of a deck.
+    struct Stalled;
+
+    impl Read for Stalled {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(ErrorKind::TimedOut.into())
+        }
+    }
+
+    #[test]
+    fn failed_reads_refuse_the_transfer() {
+        let text = BufReader::new("#name:Cut\nhola\thello\nadiós\tgood".as_bytes().chain(Stalled));
+        assert!(matches!(read_transfer(text), Err(Error::Network(_))));
+        let json = BufReader::new("{ \"name\": \"Cut\", \"cards\": [".as_bytes().chain(Stalled));
+        assert!(matches!(read_transfer(json), Err(Error::Network(_))));
+        assert!(!read_pairing(&mut BufReader::new(Stalled), "042917"));
+    }
+
+    #[test]
+    fn pairing_code_gates_imports() {
+        let mut data = "#code: 042917 \n#name:Paired\nno tab\na\tb\n".as_bytes();
+        assert!(read_pairing(&mut data, "042917"));
+        // Lines count from the sender's file, after the code line
+        let results = read_transfer(data).unwrap();
+        assert_eq!(results[0].report.issues, vec![ParseIssue { line: 2, reason: SkipReason::MissingTab }]);
+
+        assert!(!read_pairing(&mut "#code:042918\n#name:x\n".as_bytes(), "042917"));
+        assert!(!read_pairing(&mut "#name:x\n#code:042917\n".as_bytes(), "042917"));
+        assert!(!read_pairing(&mut "".as_bytes(), "042917"));
+        assert!(!read_pairing(&mut format!("#code:042917{}\n", " ".repeat(MAX_LINE_BYTES)).as_bytes(), "042917"));
+    }
+
+    #[test]
+    fn encrypted_exports_import() {
+        let tsv = cards_to_tsv("Secret", &[Card::new("hola", "hello")]);
+        let sealed = crypto::seal_with(tsv.as_bytes(), "correct horse", 1000, [1; 16], [2; 12]);
+        let results = open_encrypted(&sealed, "correct horse").unwrap();
+        assert_eq!((results[0].name.as_deref(), results[0].cards[0].back.as_str()), (Some("Secret"), "hello"));
+        assert!(matches!(open_encrypted(&sealed, "wrong horse"), Err(Error::Crypto(_))));
+
+        let many: String = (0..MAX_BUNDLE_DECKS + 5).map(|i| format!("#name:D{}\na\tb\n", i)).collect();
+        let sealed = crypto::seal_with(many.as_bytes(), "correct horse", 1000, [1; 16], [2; 12]);
+        let results = open_encrypted(&sealed, "correct horse").unwrap();
+        assert_eq!((results.len(), results[MAX_BUNDLE_DECKS - 1].report.decks_dropped), (MAX_BUNDLE_DECKS, 5));
+        let sealed = crypto::seal_with(b"FCBK\x01\0\0\0", "correct horse", 1000, [1; 16], [2; 12]);
+        assert!(matches!(open_encrypted(&sealed, "correct horse"), Err(Error::Parse(_))));
+
+        let settings = ExportSettings { timeout_secs: 120, format: DeckFormat::Json, encrypt: true };
+        assert_eq!(deserialize_export_settings(&serialize_export_settings(&settings)), Some(settings));
+        let v2 = [2, 30, 0, 0, 0, 1];
+        assert_eq!(deserialize_export_settings(&v2).map(|s| (s.format, s.encrypt)), Some((DeckFormat::Csv, false)));
     }
 }
@@ -0,0 +1,582 @@
+//! JSON deck interchange, carrying everything TSV can't: card ids, tags,
+//! deck settings and review state.
+//!
+//! ```text
+//! {
+//!   "format": "precursor-flashcards",
+//!   "version": 1,
+//!   "name": "Spanish",
+//!   "settings": { "scheduler": "leitner", "leitner_intervals": [1, 2, 4] },
+//!   "cards": [
+//!     { "id": "00000000000000a1", "front": "hola", "back": "hello",
+//!       "tags": ["greeting"],
+//!       "state": { "ease": 2500, "interval": 6, "reps": 2, "due": 20300,
+//!                  "leitner_box": 2, "stability": 0, "difficulty": 0 } }
+//!   ]
+//! }
+//! ```
+//!
+//! Ids are 16 hex digits in a string, since many JSON readers lose
+//! precision on large integers. `settings`, `tags`, `state` and `id` are
+//! optional on import, and so are `format` and `version` for hand-written
+//! files; a document that has them with other values is refused rather
+//! than guessed at. Numbers are integers only, as on the device.
+
+use std::collections::BTreeMap;
+
+use crate::deck::Card;
+use crate::import::{CardCollector, ImportResult, MAX_BUNDLE_DECKS, SkipReason, parse_card_id};
+use crate::scheduler::{CardState, DeckSettings, MAX_LEITNER_BOXES, SchedulerKind};
+use crate::storage::valid_deck_name;
+
+const FORMAT_NAME: &str = "precursor-flashcards";
+const JSON_VERSION: i64 = 1;
+/// Nesting limit, so a hostile payload can't exhaust the stack.
+const MAX_DEPTH: usize = 16;
+
+/// A parsed JSON value. Objects keep their members in order and the line
+/// they start on, and arrays the line each item starts on, for the parse
+/// report.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array { lines: Vec<usize>, items: Vec<Value> },
+    Object { line: usize, members: Vec<(String, Value)> },
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object { members, .. } => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Number(n) => u32::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array { items, .. } => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Array items with the line each starts on; none if this isn't an array.
+    fn lined_items(&self) -> impl Iterator<Item = (usize, &Value)> {
+        let (lines, items): (&[usize], &[Value]) = match self {
+            Value::Array { lines, items } => (lines, items),
+            _ => (&[], &[]),
+        };
+        lines.iter().copied().zip(items)
+    }
+}
+
+/// True if the text looks like a JSON document rather than a card list.
+pub fn is_json(data: &str) -> bool {
+    data.trim_start_matches('\u{feff}').trim_start().starts_with('{')
+}
+
+/// Parse a JSON deck. Settings and review state, when present, are returned
+/// alongside the cards; a syntax error is reported on the line it occurs.
+pub fn parse_json(data: &str) -> ImportResult {
+    match parse_value(data.trim_start_matches('\u{feff}')) {
+        Ok(root) => read_deck(&root),
+        Err(line) => refused(line, SkipReason::InvalidJson),
+    }
+}
+
+/// Parse a JSON document holding one deck, or several in a `decks` array:
+/// `{ "format": "precursor-flashcards", "version": 1, "decks": [ {deck}, ... ] }`
+/// Decks past `MAX_BUNDLE_DECKS` are dropped and counted on the last one kept.
+pub fn parse_json_decks(data: &str) -> Vec<ImportResult> {
+    let root = match parse_value(data.trim_start_matches('\u{feff}')) {
+        Ok(root) => root,
+        Err(line) => return vec![refused(line, SkipReason::InvalidJson)],
+    };
+    match root.get("decks").and_then(Value::as_array) {
+        Some(decks) => match check_header(&root) {
+            Ok(()) => {
+                let mut results: Vec<_> = decks.iter().take(MAX_BUNDLE_DECKS).map(read_deck).collect();
+                if let Some(last) = results.last_mut() {
+                    last.report.decks_dropped = decks.len().saturating_sub(MAX_BUNDLE_DECKS);
+                }
+                results
+            }
+            Err(reason) => vec![refused(object_line(&root), reason)],
+        },
+        None => vec![read_deck(&root)],
+    }
+}
+
+/// A document that yields no cards, with the reason on `line`.
+fn refused(line: usize, reason: SkipReason) -> ImportResult {
+    let mut collector = CardCollector::new();
+    collector.skip(line, reason);
+    collector.finish(None)
+}
+
+/// Check `format` and `version`, when present, against what `deck_to_json`
+/// writes.
+fn check_header(root: &Value) -> Result<(), SkipReason> {
+    match root.get("format") {
+        Some(Value::String(format)) if format == FORMAT_NAME => {}
+        Some(_) => return Err(SkipReason::UnknownJsonFormat),
+        None => {}
+    }
+    match root.get("version") {
+        Some(Value::Number(JSON_VERSION)) | None => Ok(()),
+        Some(_) => Err(SkipReason::UnsupportedJsonVersion),
+    }
+}
+
+fn object_line(value: &Value) -> usize {
+    match value {
+        Value::Object { line, .. } => *line,
+        _ => 1,
+    }
+}
+
+fn read_deck(root: &Value) -> ImportResult {
+    if let Err(reason) = check_header(root) {
+        return refused(object_line(root), reason);
+    }
+    let mut collector = CardCollector::new();
+    let mut name = root.get("name").and_then(Value::as_str).map(str::to_string);
+    if name.as_deref().is_some_and(|n| !valid_deck_name(n)) {
+        collector.skip(object_line(root), SkipReason::InvalidName);
+        name = None;
+    }
+    let settings = root.get("settings").and_then(read_settings);
+
+    let mut states = BTreeMap::new();
+    for (line, card) in root.get("cards").into_iter().flat_map(Value::lined_items) {
+        let text = |key| card.get(key).and_then(Value::as_str);
+        // Anything but an object, such as a bare string, has neither
+        let (front, back) = match (text("front"), text("back")) {
+            (Some(front), Some(back)) => (front, back),
+            _ => {
+                collector.skip(line, SkipReason::MissingField);
+                continue;
+            }
+        };
+        let id = text("id").and_then(parse_card_id).unwrap_or(0);
+        let tags = card
+            .get("tags")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_str)
+            .flat_map(str::split_whitespace)
+            .map(str::to_string)
+            .collect();
+        if let Some(state) = card.get("state").and_then(read_state) {
+            if id != 0 {
+                states.insert(id, state);
+            }
+        }
+        collector.add(line, front, back, id, tags);
+    }
+
+    let mut result = collector.finish(name);
+    result.settings = settings;
+    if !states.is_empty() {
+        // Cards whose id was taken by an earlier card were given a new one
+        // and start fresh
+        let matched = result.cards.iter().map(|c| states.get(&c.id).copied().unwrap_or_default()).collect();
+        result.states = Some(matched);
+    }
+    result
+}
+
+fn read_settings(value: &Value) -> Option<DeckSettings> {
+    let mut settings = DeckSettings::new();
+    settings.scheduler = match value.get("scheduler")?.as_str()? {
+        "sm2" => SchedulerKind::Sm2,
+        "leitner" => SchedulerKind::Leitner,
+        "fsrs" => SchedulerKind::Fsrs,
+        _ => return None,
+    };
+    if let Some(intervals) = value.get("leitner_intervals").and_then(Value::as_array) {
+        let intervals: Vec<u32> = intervals.iter().filter_map(Value::as_u32).collect();
+        if !intervals.is_empty() && intervals.len() <= MAX_LEITNER_BOXES {
+            settings.leitner_intervals = intervals;
+        }
+    }
+    Some(settings)
+}
+
+fn read_state(value: &Value) -> Option<CardState> {
+    let field = |key| value.get(key).and_then(Value::as_u32);
+    let defaults = CardState::new();
+    Some(CardState {
+        ease: field("ease").map_or(defaults.ease, |e| e.min(u16::MAX as u32) as u16),
+        interval: field("interval")?,
+        reps: field("reps").unwrap_or(0),
+        due: field("due")?,
+        leitner_box: field("leitner_box").map_or(1, |b| b.clamp(1, MAX_LEITNER_BOXES as u32) as u8),
+        stability: field("stability").unwrap_or(0),
+        difficulty: field("difficulty").unwrap_or(0),
+    })
+}
+
+/// Write a whole deck as JSON. `states` runs parallel to `cards`; state is
+/// only written for cards that have been reviewed.
+pub fn deck_to_json(name: &str, settings: &DeckSettings, cards: &[Card], states: &[CardState]) -> String {
+    let scheduler = match settings.scheduler {
+        SchedulerKind::Sm2 => "sm2",
+        SchedulerKind::Leitner => "leitner",
+        SchedulerKind::Fsrs => "fsrs",
+    };
+    let intervals: Vec<String> = settings.leitner_intervals.iter().map(|d| d.to_string()).collect();
+    let mut out = String::from("{\n");
+    out.push_str(&format!("  \"format\": \"{}\",\n  \"version\": {},\n", FORMAT_NAME, JSON_VERSION));
+    out.push_str(&format!("  \"name\": {},\n", quote(name)));
+    out.push_str(&format!(
+        "  \"settings\": {{ \"scheduler\": \"{}\", \"leitner_intervals\": [{}] }},\n",
+        scheduler,
+        intervals.join(", ")
+    ));
+    out.push_str("  \"cards\": [");
+    for (i, card) in cards.iter().enumerate() {
+        out.push_str(if i == 0 { "\n" } else { ",\n" });
+        let tags: Vec<String> = card.tags.iter().map(|t| quote(t)).collect();
+        out.push_str(&format!(
+            "    {{ \"id\": \"{:016x}\", \"front\": {}, \"back\": {}, \"tags\": [{}]",
+            card.id,
+            quote(&card.front),
+            quote(&card.back),
+            tags.join(", ")
+        ));
+        match states.get(i) {
+            Some(s) if *s != CardState::new() => out.push_str(&format!(
+                ",\n      \"state\": {{ \"ease\": {}, \"interval\": {}, \"reps\": {}, \"due\": {}, \
+                 \"leitner_box\": {}, \"stability\": {}, \"difficulty\": {} }} }}",
+                s.ease, s.interval, s.reps, s.due, s.leitner_box, s.stability, s.difficulty
+            )),
+            _ => out.push_str(" }"),
+        }
+    }
+    out.push_str("\n  ]\n}\n");
+    out
+}
+
+/// A JSON string literal, escaping quotes, backslashes and control characters.
+fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parse a complete JSON document, or return the line of the first error.
+pub fn parse_value(text: &str) -> Result<Value, usize> {
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(_) => Err(parser.line),
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '\n' => self.line += 1,
+                ' ' | '\t' | '\r' => {}
+                _ => break,
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), usize> {
+        self.skip_whitespace();
+        if self.chars.next() == Some(c) { Ok(()) } else { Err(self.line) }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, usize> {
+        if depth > MAX_DEPTH {
+            return Err(self.line);
+        }
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Null),
+            _ => Err(self.line),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, usize> {
+        let line = self.line;
+        self.chars.next();
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Value::Object { line, members });
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.line);
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = self.value(depth + 1)?;
+            members.push((key, value));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object { line, members }),
+                _ => return Err(self.line),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, usize> {
+        self.chars.next();
+        let mut lines = Vec::new();
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Value::Array { lines, items });
+        }
+        loop {
+            self.skip_whitespace();
+            lines.push(self.line);
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array { lines, items }),
+                _ => return Err(self.line),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        self.chars.next();
+        let mut out = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return Err(self.line),
+                Some('"') => return Ok(out),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('u') => {
+                        let high = self.hex4()?;
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            // A surrogate pair encodes one character above U+FFFF
+                            if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                                return Err(self.line);
+                            }
+                            let low = self.hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(self.line);
+                            }
+                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                        } else {
+                            high
+                        };
+                        out.push(char::from_u32(code).ok_or(self.line)?);
+                    }
+                    _ => return Err(self.line),
+                },
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, usize> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16)).ok_or(self.line)?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// Integers only; fractions and exponents are refused.
+    fn number(&mut self) -> Result<Value, usize> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c == '-' || c.is_ascii_digit()) {
+                break;
+            }
+            text.push(c);
+            self.chars.next();
+        }
+        if matches!(self.chars.peek(), Some('.' | 'e' | 'E')) {
+            return Err(self.line);
+        }
+        text.parse().map(Value::Number).map_err(|_| self.line)
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, usize> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(self.line);
+            }
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::assign_ids;
+    use crate::import::tests::{XorShift, random_field, random_name};
+    use crate::import::{DeckFormat, ParseIssue, detect_format, export_text, parse_import};
+
+    #[test]
+    fn json_round_trips() {
+        let mut rng = XorShift(0x1405_7b7e_f767_814f);
+        let settings = DeckSettings { scheduler: SchedulerKind::Leitner, leitner_intervals: vec![1, 3, 9] };
+        for _ in 0..200 {
+            let name = random_name(&mut rng);
+            let mut cards: Vec<Card> = (0..1 + rng.below(10))
+                .map(|_| Card {
+                    id: rng.next() | 1,
+                    front: random_field(&mut rng),
+                    back: random_field(&mut rng),
+                    tags: (0..rng.below(3)).map(|i| format!("tag{}", i)).collect(),
+                })
+                .collect();
+            assign_ids(&mut cards);
+            let states: Vec<CardState> = cards
+                .iter()
+                .map(|_| CardState {
+                    interval: rng.below(100) as u32,
+                    due: rng.below(30000) as u32,
+                    ..CardState::new()
+                })
+                .collect();
+
+            let json = export_text(DeckFormat::Json, &name, &cards, &settings, &states);
+            assert_eq!(detect_format(&json), DeckFormat::Json);
+            let result = parse_import(&json);
+            assert_eq!(result.report.issues, vec![], "{}", json);
+            assert_eq!(result.name.as_deref(), Some(name.as_str()));
+            assert_eq!(result.cards.len(), cards.len());
+            for (parsed, card) in result.cards.iter().zip(&cards) {
+                assert_eq!(
+                    (parsed.id, &parsed.front, &parsed.back, &parsed.tags),
+                    (card.id, &card.front, &card.back, &card.tags)
+                );
+            }
+            assert_eq!(result.settings.as_ref(), Some(&settings));
+            assert_eq!(result.states.as_deref(), Some(&states[..]));
+        }
+    }
+
+    #[test]
+    fn json_errors_are_reported() {
+        let result = parse_import("{\n  \"name\": \"x\",\n  \"cards\": [1.5]\n}");
+        assert_eq!(result.report.issues, vec![ParseIssue { line: 3, reason: SkipReason::InvalidJson }]);
+        let data = "{ \"cards\": [\n  { \"front\": \"a\" },\n  \
+                    { \"front\": \"\\ud83d\\ude00\", \"back\": \"b\" }\n] }";
+        let result = parse_import(data);
+        assert_eq!(result.report.issues, vec![ParseIssue { line: 2, reason: SkipReason::MissingField }]);
+        assert_eq!(result.cards[0].front, "😀");
+        assert!(result.settings.is_none() && result.states.is_none());
+
+        let data = "{ \"name\": \"x\\ny\",\n  \"cards\": [\n  \"hola\",\n  \
+                    { \"front\": \"a\", \"back\": \"b\" }, 7\n] }";
+        let result = parse_import(data);
+        assert_eq!(result.name, None);
+        assert_eq!(
+            result.report.issues,
+            vec![
+                ParseIssue { line: 1, reason: SkipReason::InvalidName },
+                ParseIssue { line: 3, reason: SkipReason::MissingField },
+                ParseIssue { line: 4, reason: SkipReason::MissingField },
+            ]
+        );
+        assert_eq!(result.cards.len(), 1);
+    }
+
+    #[test]
+    fn foreign_documents_are_refused() {
+        let card = "\"cards\": [ { \"front\": \"a\", \"back\": \"b\" } ]";
+        let issues = |data: &str| parse_json(data).report.issues;
+        let ours = format!("{{ \"format\": \"{}\", \"version\": {}, {} }}", FORMAT_NAME, JSON_VERSION, card);
+        assert_eq!(parse_json(&ours).cards.len(), 1);
+        assert_eq!(
+            issues(&format!("{{ \"format\": \"anki\", {} }}", card)),
+            vec![ParseIssue { line: 1, reason: SkipReason::UnknownJsonFormat }]
+        );
+        assert_eq!(
+            issues(&format!("\n{{ \"version\": 2, {} }}", card)),
+            vec![ParseIssue { line: 2, reason: SkipReason::UnsupportedJsonVersion }]
+        );
+        assert_eq!(
+            issues(&format!("{{ \"version\": \"1\", {} }}", card)),
+            vec![ParseIssue { line: 1, reason: SkipReason::UnsupportedJsonVersion }]
+        );
+
+        let bundle = format!("{{ \"format\": \"other\", \"decks\": [ {{ {} }} ] }}", card);
+        let results = parse_json_decks(&bundle);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].report.issues, vec![ParseIssue { line: 1, reason: SkipReason::UnknownJsonFormat }]);
+        let bundle = format!("{{ \"decks\": [ {{ {} }}, {{ \"version\": 9, {} }} ] }}", card, card);
+        let results = parse_json_decks(&bundle);
+        assert_eq!(results[0].cards.len(), 1);
+        assert_eq!(results[1].report.issues[0].reason, SkipReason::UnsupportedJsonVersion);
+    }
+
+    #[test]
+    fn json_bundles_are_capped() {
+        let decks: Vec<_> = (0..MAX_BUNDLE_DECKS + 3).map(|i| format!("{{ \"name\": \"D{}\" }}", i)).collect();
+        let results = parse_json_decks(&format!("{{ \"decks\": [ {} ] }}", decks.join(", ")));
+        assert_eq!(results.len(), MAX_BUNDLE_DECKS);
+        assert_eq!(results[MAX_BUNDLE_DECKS - 1].report.decks_dropped, 3);
+        assert_eq!(results[0].report.decks_dropped, 0);
+    }
+}
@@ -1,18 +1,41 @@
 #![cfg_attr(target_os = "none", no_std)]
 #![cfg_attr(target_os = "none", no_main)]
 
+mod anki;
+mod backup;
+mod crypto;
+mod csv;
 mod deck;
+mod error;
+mod fsrs;
 mod import;
+mod json;
+mod markdown;
+mod merge;
+mod review_log;
+mod scheduler;
 mod storage;
 mod ui;
 
+use std::time::{Duration, Instant};
+
 use num_traits::{FromPrimitive, ToPrimitive};
 
 use gam::UxRegistration;
 use gam::menu::*;
 
+use crate::backup::{Backup, BackupSummary};
+use crate::crypto::{CryptoError, Sealer};
 use crate::deck::{Card, DeckMeta};
+use crate::error::Error;
+use crate::import::{
+    BundleEntry, BundleOutcome, ExportJob, ExportSettings, ImportListener, ImportResult, Transfer, UnlockJob,
+};
+use crate::merge::MergeSummary;
+use crate::review_log::ReviewEntry;
+use crate::scheduler::{CardState, DeckSettings, Grade, SchedulerKind};
 use crate::storage::DeckStorage;
+use crate::ui::{Severity, StatusBar};
 
 const SERVER_NAME: &str = "_Flashcards_";
 const APP_NAME: &str = "Flashcards";
@@ -22,12 +45,26 @@ const KEY_F1: char = '\u{0011}';
 const KEY_F2: char = '\u{0012}';
 const KEY_F3: char = '\u{0013}';
 const KEY_F4: char = '\u{0014}';
+const KEY_BACKSPACE: char = '\u{0008}';
+
+/// True for a key that types a character. GAM sends the arrow keys as arrow
+/// glyphs, which are navigation, not text.
+fn is_text_key(c: char) -> bool {
+    !c.is_control() && !matches!(c, '↑' | '↓' | '←' | '→')
+}
+/// Cards listed on the import preview screen.
+const PREVIEW_CARDS: usize = 3;
 
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 enum AppOp {
     Redraw = 0,
     Rawkeys,
     FocusChange,
+    StatusExpire,
+    ImportDone,
+    RestoreDone,
+    ExportUpdate,
+    UnlockDone,
     Quit,
 }
 
@@ -37,6 +74,28 @@ enum AppState {
     CardReview,
     DeckMenu { confirm_delete: bool },
     ImportWait,
+    ImportPreview { renaming: bool },
+    ImportSummary(MergeSummary),
+    /// Results of a multi-deck import, scrolled to this entry.
+    BundleSummary { scroll: usize },
+    RestoreWait,
+    /// A verified backup is waiting for the user to confirm the restore.
+    RestoreConfirm,
+    /// Sending one deck, or with `backup` the whole library.
+    ExportWait { backup: bool },
+    /// Typing a passphrase, shown masked.
+    Passphrase(PassphraseStep),
+}
+
+/// What a passphrase being typed is for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PassphraseStep {
+    /// Choosing one for an encrypted export.
+    Export,
+    /// Typing it again, since a typo would lock the file.
+    ConfirmExport,
+    /// Opening an encrypted file that was received.
+    Unlock,
 }
 
 struct FlashcardApp {
@@ -53,19 +112,53 @@ struct FlashcardApp {
     scroll_offset: usize,
     // Card review state
     current_deck_name: String,
+    settings: DeckSettings,
+    review_count: usize,
     cards: Vec<Card>,
+    states: Vec<CardState>,
+    // Indices into `cards` for this session; `current_card` indexes this
+    queue: Vec<usize>,
     current_card: usize,
     showing_back: bool,
+    shown_at: Instant,
+    reviewed: usize,
     // Menu overlay state
     menu_visible: bool,
     menu_cursor: usize,
     help_visible: bool,
+    status: StatusBar,
+    // Connection to our own server, for worker threads to report back
+    self_cid: xous::CID,
+    import_listener: Option<ImportListener>,
+    // Parsed import awaiting confirmation on the preview screen
+    pending_import: Option<ImportResult>,
+    pending_name: String,
+    rename_buffer: String,
+    // When merging, drop deck cards that are missing from the import
+    import_remove_missing: bool,
+    // Per-deck results of the last multi-deck import
+    bundle_entries: Vec<BundleEntry>,
+    restore_listener: Option<ImportListener<(Backup, BackupSummary)>>,
+    // Received backup awaiting confirmation; nothing is overwritten until then
+    pending_restore: Option<(Backup, BackupSummary)>,
+    passphrase: String,
+    // First entry of an export passphrase, while it is typed again
+    first_passphrase: String,
+    // Encrypted import waiting for its passphrase
+    pending_encrypted: Option<Vec<u8>>,
+    // Decryption running with the passphrase just entered
+    unlock_job: Option<UnlockJob>,
+    export_job: Option<ExportJob>,
+    export_settings: ExportSettings,
+    netmgr: net::NetManager,
+    trng: trng::Trng,
     should_quit: bool,
 }
 
 impl FlashcardApp {
     fn new(xns: &xous_names::XousNames, sid: xous::SID) -> Self {
         let gam = gam::Gam::new(xns).expect("can't connect to GAM");
+        let trng = trng::Trng::new(xns).expect("can't connect to TRNG");
 
         let token = gam
             .register_ux(UxRegistration {
@@ -85,9 +178,11 @@ impl FlashcardApp {
         let content = gam.request_content_canvas(token).expect("couldn't get canvas");
         let screensize = gam.get_canvas_bounds(content).expect("couldn't get dimensions");
 
+        let self_cid = xous::connect(sid).expect("can't connect to self");
         let storage = DeckStorage::new();
         storage.ensure_demo_deck();
         let decks = storage.list_decks();
+        let export_settings = storage.load_export_settings();
 
         Self {
             gam,
@@ -100,17 +195,46 @@ impl FlashcardApp {
             cursor: 0,
             scroll_offset: 0,
             current_deck_name: String::new(),
+            settings: DeckSettings::new(),
+            review_count: 0,
             cards: Vec::new(),
+            states: Vec::new(),
+            queue: Vec::new(),
             current_card: 0,
             showing_back: false,
+            shown_at: Instant::now(),
+            reviewed: 0,
             menu_visible: false,
             menu_cursor: 0,
             help_visible: false,
+            status: StatusBar::new(self_cid, AppOp::StatusExpire.to_usize().unwrap()),
+            self_cid,
+            import_listener: None,
+            pending_import: None,
+            pending_name: String::new(),
+            rename_buffer: String::new(),
+            import_remove_missing: false,
+            bundle_entries: Vec::new(),
+            restore_listener: None,
+            pending_restore: None,
+            passphrase: String::new(),
+            first_passphrase: String::new(),
+            pending_encrypted: None,
+            unlock_job: None,
+            export_job: None,
+            export_settings,
+            netmgr: net::NetManager::new(),
+            trng,
             should_quit: false,
         }
     }
 
     fn redraw(&self) {
+        self.draw_screen();
+        self.status.draw(&self.gam, self.content, self.screensize);
+    }
+
+    fn draw_screen(&self) {
         if self.help_visible {
             ui::draw_help(&self.gam, self.content, self.screensize, self.help_text());
             return;
@@ -138,17 +262,26 @@ impl FlashcardApp {
                 );
             }
             AppState::CardReview => {
-                if let Some(card) = self.cards.get(self.current_card) {
+                if let Some(&idx) = self.queue.get(self.current_card) {
                     ui::draw_card_review(
                         &self.gam,
                         self.content,
                         self.screensize,
                         &self.current_deck_name,
-                        card,
+                        &self.cards[idx],
                         self.current_card,
-                        self.cards.len(),
+                        self.queue.len(),
                         self.showing_back,
                     );
+                } else {
+                    ui::draw_review_done(
+                        &self.gam,
+                        self.content,
+                        self.screensize,
+                        &self.current_deck_name,
+                        self.reviewed,
+                        self.cards.len(),
+                    );
                 }
             }
             AppState::DeckMenu { confirm_delete } => {
@@ -159,17 +292,119 @@ impl FlashcardApp {
                     self.screensize,
                     &self.current_deck_name,
                     card_count,
+                    &format!(
+                        "Scheduler: {}\nReviews: {}\nExport: {}, {}s timeout{}",
+                        self.settings.describe(),
+                        self.review_count,
+                        self.export_settings.format.extension().to_uppercase(),
+                        self.export_settings.timeout_secs,
+                        if self.export_settings.encrypt { ", encrypted" } else { "" }
+                    ),
                     *confirm_delete,
                 );
             }
             AppState::ImportWait => {
-                ui::draw_import_wait(
+                if let Some(listener) = &self.import_listener {
+                    ui::draw_import_wait(
+                        &self.gam,
+                        self.content,
+                        self.screensize,
+                        import::listen_port(),
+                        listener.code(),
+                    );
+                }
+            }
+            AppState::ImportPreview { renaming } => {
+                if let Some(result) = &self.pending_import {
+                    let name = if *renaming {
+                        format!("{}_", self.rename_buffer)
+                    } else {
+                        self.pending_name.clone()
+                    };
+                    ui::draw_import_preview(
+                        &self.gam,
+                        self.content,
+                        self.screensize,
+                        &name,
+                        &result.cards[..result.cards.len().min(PREVIEW_CARDS)],
+                        &self.import_preview_details(result, *renaming),
+                    );
+                }
+            }
+            AppState::ImportSummary(summary) => {
+                ui::draw_import_summary(
                     &self.gam,
                     self.content,
                     self.screensize,
-                    import::listen_port(),
+                    &self.current_deck_name,
+                    summary,
                 );
             }
+            AppState::BundleSummary { scroll } => {
+                ui::draw_bundle_summary(&self.gam, self.content, self.screensize, &self.bundle_entries, *scroll);
+            }
+            AppState::RestoreWait => {
+                if let Some(listener) = &self.restore_listener {
+                    let port = import::listen_port();
+                    ui::draw_restore_wait(&self.gam, self.content, self.screensize, port, listener.code());
+                }
+            }
+            AppState::RestoreConfirm => {
+                if let Some((_, summary)) = &self.pending_restore {
+                    ui::draw_restore_confirm(&self.gam, self.content, self.screensize, summary, self.decks.len());
+                }
+            }
+            AppState::Passphrase(step) => {
+                let (title, prompt, note) = match step {
+                    PassphraseStep::Export => (
+                        "Encrypt Export",
+                        "Passphrase:",
+                        format!(
+                            "At least {} characters. The exported file can't be opened without it.",
+                            crypto::MIN_PASSPHRASE_CHARS
+                        ),
+                    ),
+                    PassphraseStep::ConfirmExport => ("Encrypt Export", "Repeat passphrase:", String::new()),
+                    PassphraseStep::Unlock if self.unlock_job.is_some() => {
+                        ("Encrypted Import", "Passphrase:", "Decrypting...".to_string())
+                    }
+                    PassphraseStep::Unlock => (
+                        "Encrypted Import",
+                        "Passphrase:",
+                        format!(
+                            "{} encrypted bytes received. Enter the passphrase they were exported with.",
+                            self.pending_encrypted.as_ref().map_or(0, |d| d.len())
+                        ),
+                    ),
+                };
+                let typed = self.passphrase.chars().count();
+                ui::draw_passphrase(&self.gam, self.content, self.screensize, title, prompt, typed, &note);
+            }
+            AppState::ExportWait { backup } => {
+                if let Some(job) = &self.export_job {
+                    let ip = match self.netmgr.get_ipv4_config().map(|conf| conf.addr) {
+                        Some([a, b, c, d]) => format!("{}.{}.{}.{}", a, b, c, d),
+                        None => "<device-ip>".to_string(),
+                    };
+                    let (title, file) = if *backup {
+                        ("Backup All", "flashcards.fcbk".to_string())
+                    } else {
+                        let suffix = if self.export_settings.encrypt { ".enc" } else { "" };
+                        ("Export Deck", format!("deck.{}{}", self.export_settings.format.extension(), suffix))
+                    };
+                    let command = format!("nc {} {} > {}", ip, import::export_port(), file);
+                    ui::draw_export_wait(
+                        &self.gam,
+                        self.content,
+                        self.screensize,
+                        title,
+                        import::export_port(),
+                        &command,
+                        job.total_bytes(),
+                        job.progress(),
+                    );
+                }
+            }
         }
     }
 
@@ -221,20 +456,65 @@ impl FlashcardApp {
             AppState::DeckMenu { confirm_delete } => self.handle_key_deck_menu(key, confirm_delete),
             AppState::ImportWait => {
                 if key == 'q' {
-                    self.state = AppState::DeckList;
-                    self.refresh_deck_list();
+                    self.cancel_import();
+                }
+            }
+            AppState::ImportPreview { renaming } => self.handle_key_import_preview(key, renaming),
+            AppState::ImportSummary(_) => {
+                self.state = AppState::DeckList;
+                self.redraw();
+            }
+            AppState::BundleSummary { scroll } => self.handle_key_bundle_summary(key, scroll),
+            AppState::RestoreWait => {
+                if key == 'q' {
+                    self.cancel_restore();
+                }
+            }
+            AppState::RestoreConfirm => match key {
+                'y' => {
+                    self.confirm_restore();
                     self.redraw();
                 }
+                'n' | 'q' => {
+                    self.discard_restore();
+                    self.redraw();
+                }
+                _ => {}
+            },
+            AppState::ExportWait { .. } => {
+                if key == 'q' {
+                    self.cancel_export();
+                }
             }
+            AppState::Passphrase(step) => self.handle_key_passphrase(key, step),
         }
     }
 
     fn menu_items(&self) -> &'static [&'static str] {
         match &self.state {
-            AppState::DeckList => &["Help", "Import Deck (TCP)", "Manage Deck"],
+            AppState::DeckList => {
+                &["Help", "Import Deck (TCP)", "Manage Deck", "Backup All (TCP)", "Restore Backup (TCP)"]
+            }
             AppState::CardReview => &["Help", "Flip Card", "Next Card", "Shuffle", "Back to List"],
-            AppState::DeckMenu { .. } => &["Help", "Export (TCP)", "Delete Deck", "Back to List"],
-            AppState::ImportWait => &["Help"],
+            AppState::DeckMenu { .. } => &[
+                "Help",
+                "Export (TCP)",
+                "Export Format",
+                "Export Timeout",
+                "Encrypt Exports",
+                "Switch Scheduler",
+                "Leitner Boxes",
+                "Delete Deck",
+                "Back to List",
+            ],
+            AppState::ImportPreview { .. } => &["Help", "Accept", "Rename", "Merge", "Discard"],
+            AppState::RestoreConfirm => &["Help", "Restore", "Cancel"],
+            AppState::ImportWait
+            | AppState::ImportSummary(_)
+            | AppState::BundleSummary { .. }
+            | AppState::RestoreWait
+            | AppState::ExportWait { .. }
+            | AppState::Passphrase(_) => &["Help"],
         }
     }
 
@@ -258,17 +538,13 @@ impl FlashcardApp {
                 match self.menu_cursor {
                     0 => { self.help_visible = true; }
                     1 => {
-                        self.state = AppState::ImportWait;
-                        self.redraw();
-                        self.do_import();
-                        return;
+                        self.start_import();
                     }
                     2 => {
-                        if let Some(deck_meta) = self.decks.get(self.cursor) {
-                            self.current_deck_name = deck_meta.name.clone();
-                            self.state = AppState::DeckMenu { confirm_delete: false };
-                        }
+                        self.open_deck_menu();
                     }
+                    3 => self.start_backup(),
+                    4 => self.start_restore(),
                     _ => {}
                 }
             }
@@ -277,16 +553,16 @@ impl FlashcardApp {
                     0 => { self.help_visible = true; }
                     1 => { self.showing_back = !self.showing_back; }
                     2 => {
-                        if self.current_card + 1 < self.cards.len() {
+                        if self.current_card + 1 < self.queue.len() {
                             self.current_card += 1;
-                            self.showing_back = false;
+                            self.show_front();
                         }
                     }
                     3 => {
                         // Shuffle cards
                         self.shuffle_cards();
                         self.current_card = 0;
-                        self.showing_back = false;
+                        self.show_front();
                     }
                     4 => {
                         self.state = AppState::DeckList;
@@ -299,26 +575,58 @@ impl FlashcardApp {
                 match self.menu_cursor {
                     0 => { self.help_visible = true; }
                     1 => {
-                        // Export via TCP
-                        if let Some(cards) = self.storage.load_deck(&self.current_deck_name) {
-                            match import::export_via_tcp(&self.current_deck_name, &cards) {
-                                Ok(bytes) => log::info!("Exported {} bytes", bytes),
-                                Err(e) => log::error!("Export failed: {}", e),
-                            }
-                        }
+                        self.export_current_deck();
                     }
                     2 => {
+                        self.cycle_export_format();
+                    }
+                    3 => {
+                        self.cycle_export_timeout();
+                    }
+                    4 => {
+                        self.toggle_export_encryption();
+                    }
+                    5 => {
+                        self.cycle_scheduler();
+                    }
+                    6 => {
+                        self.cycle_leitner_boxes();
+                    }
+                    7 => {
                         // Trigger delete confirmation
                         self.state = AppState::DeckMenu { confirm_delete: true };
                     }
-                    3 => {
+                    8 => {
                         self.state = AppState::DeckList;
                         self.refresh_deck_list();
                     }
                     _ => {}
                 }
             }
-            AppState::ImportWait => {
+            AppState::ImportPreview { .. } => {
+                match self.menu_cursor {
+                    0 => { self.help_visible = true; }
+                    1 => self.accept_import(),
+                    2 => self.start_rename(),
+                    3 => self.merge_import(),
+                    4 => self.discard_import(),
+                    _ => {}
+                }
+            }
+            AppState::RestoreConfirm => {
+                match self.menu_cursor {
+                    0 => { self.help_visible = true; }
+                    1 => self.confirm_restore(),
+                    2 => self.discard_restore(),
+                    _ => {}
+                }
+            }
+            AppState::ImportWait
+            | AppState::ImportSummary(_)
+            | AppState::BundleSummary { .. }
+            | AppState::RestoreWait
+            | AppState::ExportWait { .. }
+            | AppState::Passphrase(_) => {
                 if self.menu_cursor == 0 {
                     self.help_visible = true;
                 }
@@ -342,9 +650,9 @@ impl FlashcardApp {
         if self.menu_visible { self.menu_visible = false; }
         // F3 = Next card (during review)
         if let AppState::CardReview = &self.state {
-            if self.current_card + 1 < self.cards.len() {
+            if self.current_card + 1 < self.queue.len() {
                 self.current_card += 1;
-                self.showing_back = false;
+                self.show_front();
             }
         }
         self.redraw();
@@ -378,7 +686,34 @@ impl FlashcardApp {
                 self.should_quit = true;
             }
             AppState::ImportWait => {
-                // Can't interrupt blocking import
+                self.cancel_import();
+            }
+            AppState::ImportPreview { renaming: true } => {
+                self.state = AppState::ImportPreview { renaming: false };
+                self.redraw();
+            }
+            AppState::ImportPreview { renaming: false } => {
+                self.discard_import();
+                self.redraw();
+            }
+            AppState::ImportSummary(_) | AppState::BundleSummary { .. } => {
+                self.state = AppState::DeckList;
+                self.redraw();
+            }
+            AppState::RestoreWait => {
+                self.cancel_restore();
+            }
+            AppState::RestoreConfirm => {
+                self.discard_restore();
+                self.redraw();
+            }
+            AppState::ExportWait { .. } => {
+                self.cancel_export();
+            }
+            AppState::Passphrase(step) => {
+                let step = *step;
+                self.cancel_passphrase(step);
+                self.redraw();
             }
         }
     }
@@ -393,6 +728,8 @@ impl FlashcardApp {
                  Enter  Open deck\n\
                  i      Import deck\n\
                  m      Manage deck\n\
+                 b      Backup all (TCP)\n\
+                 r      Restore backup\n\
                  q      Quit"
             }
             AppState::CardReview => {
@@ -402,6 +739,8 @@ impl FlashcardApp {
                  F3     Next card\n\
                  F4     Back to list\n\n\
                  Space  Flip card\n\
+                 1-4    Again/Hard/\n\
+                        Good/Easy\n\
                  <-/->  Prev/Next\n\
                  n/p    Next/Prev\n\
                  s      Shuffle deck\n\
@@ -412,6 +751,12 @@ impl FlashcardApp {
                  F1     Menu\n\
                  F4     Back to list\n\n\
                  e      Export (TCP 7879)\n\
+                 f      Export format\n\
+                        (TSV/CSV/JSON)\n\
+                 t      Export timeout\n\
+                 c      Encrypt exports\n\
+                 s      Switch scheduler\n\
+                 b      Leitner box count\n\
                  d      Delete deck\n\
                  y/n    Confirm/cancel\n\
                  q      Back to list"
@@ -422,7 +767,82 @@ impl FlashcardApp {
                  connection on\n\
                  port 7878.\n\n\
                  Send TSV file\n\
-                 from computer."
+                 from computer,\n\
+                 starting with\n\
+                 #code: and the\n\
+                 pairing code.\n\n\
+                 q/F4   Cancel"
+            }
+            AppState::ImportPreview { .. } => {
+                "IMPORT PREVIEW HELP\n\n\
+                 Nothing is saved\n\
+                 until you choose.\n\n\
+                 a      Accept\n\
+                 r      Rename\n\
+                 m      Merge into\n\
+                        same-named deck\n\
+                 x      Remove missing\n\
+                        cards on merge\n\
+                 d/F4   Discard"
+            }
+            AppState::ImportSummary(_) => {
+                "MERGE SUMMARY\n\n\
+                 Cards matched by id\n\
+                 or front text keep\n\
+                 their review state.\n\
+                 Updated cards start\n\
+                 over.\n\n\
+                 Any key continues."
+            }
+            AppState::BundleSummary { .. } => {
+                "BUNDLE SUMMARY\n\n\
+                 One line per deck:\n\
+                 new decks with\n\
+                 their card count,\n\
+                 merged decks as\n\
+                 +added ~updated\n\
+                 =unchanged\n\
+                 -removed.\n\n\
+                 j/k    Scroll\n\
+                 Other keys continue."
+            }
+            AppState::RestoreWait => {
+                "RESTORE HELP\n\n\
+                 Waiting for a\n\
+                 backup file on\n\
+                 port 7878.\n\n\
+                 It is checked\n\
+                 before anything\n\
+                 is replaced.\n\n\
+                 q/F4   Cancel"
+            }
+            AppState::RestoreConfirm => {
+                "RESTORE HELP\n\n\
+                 Restoring replaces\n\
+                 every deck, setting\n\
+                 and review history\n\
+                 with the backup.\n\n\
+                 y      Restore\n\
+                 n/F4   Cancel"
+            }
+            AppState::Passphrase(_) => {
+                "PASSPHRASE HELP\n\n\
+                 Type the\n\
+                 passphrase; it\n\
+                 is shown as *.\n\n\
+                 Enter  Continue\n\
+                 Bksp   Delete\n\
+                 F4     Cancel"
+            }
+            AppState::ExportWait { .. } => {
+                "EXPORT HELP\n\n\
+                 Waiting for TCP\n\
+                 connection on\n\
+                 port 7879.\n\n\
+                 Connect from your\n\
+                 computer before\n\
+                 the countdown ends.\n\n\
+                 q/F4   Cancel"
             }
         }
     }
@@ -446,27 +866,24 @@ impl FlashcardApp {
             '\r' | '\n' => {
                 if let Some(deck_meta) = self.decks.get(self.cursor) {
                     let name = deck_meta.name.clone();
-                    if let Some(cards) = self.storage.load_deck(&name) {
-                        self.current_deck_name = name;
-                        self.cards = cards;
-                        self.current_card = 0;
-                        self.showing_back = false;
-                        self.state = AppState::CardReview;
-                        self.redraw();
-                    }
+                    self.open_deck(name);
                 }
             }
             'i' => {
-                self.state = AppState::ImportWait;
+                self.start_import();
                 self.redraw();
-                self.do_import();
             }
             'm' => {
-                if let Some(deck_meta) = self.decks.get(self.cursor) {
-                    self.current_deck_name = deck_meta.name.clone();
-                    self.state = AppState::DeckMenu { confirm_delete: false };
-                    self.redraw();
-                }
+                self.open_deck_menu();
+                self.redraw();
+            }
+            'b' => {
+                self.start_backup();
+                self.redraw();
+            }
+            'r' => {
+                self.start_restore();
+                self.redraw();
             }
             'q' => {
                 // Signal quit - this will be processed by returning true from handle_key
@@ -478,29 +895,43 @@ impl FlashcardApp {
     }
 
     fn handle_key_card_review(&mut self, key: char) {
+        if self.showing_back {
+            if let Some(grade) = Grade::from_key(key) {
+                self.grade_current_card(grade);
+                self.redraw();
+                return;
+            }
+        }
         match key {
             '→' | 'n' => {
-                if self.current_card + 1 < self.cards.len() {
+                if self.current_card + 1 < self.queue.len() {
                     self.current_card += 1;
-                    self.showing_back = false;
+                    self.show_front();
                     self.redraw();
                 }
             }
             '←' | 'p' => {
                 if self.current_card > 0 {
                     self.current_card -= 1;
-                    self.showing_back = false;
+                    self.show_front();
                     self.redraw();
                 }
             }
-            ' ' | '\r' | '\n' => {
+            ' ' | '\r' | '\n' if self.current_card < self.queue.len() => {
                 self.showing_back = !self.showing_back;
                 self.redraw();
             }
+            'a' if self.current_card >= self.queue.len() => {
+                // Review the whole deck regardless of due dates
+                self.queue = (0..self.cards.len()).collect();
+                self.current_card = 0;
+                self.show_front();
+                self.redraw();
+            }
             's' => {
                 self.shuffle_cards();
                 self.current_card = 0;
-                self.showing_back = false;
+                self.show_front();
                 self.redraw();
             }
             'q' => {
@@ -516,7 +947,10 @@ impl FlashcardApp {
         if confirm_delete {
             match key {
                 'y' => {
-                    self.storage.delete_deck(&self.current_deck_name);
+                    match self.storage.delete_deck(&self.current_deck_name) {
+                        Ok(()) => self.status.post(Severity::Info, format!("Deleted '{}'", self.current_deck_name)),
+                        Err(e) => self.report_error(e),
+                    }
                     self.state = AppState::DeckList;
                     self.refresh_deck_list();
                     // Adjust cursor if it's now beyond the list
@@ -538,13 +972,27 @@ impl FlashcardApp {
         } else {
             match key {
                 'e' => {
-                    // Export via TCP
-                    if let Some(cards) = self.storage.load_deck(&self.current_deck_name) {
-                        match import::export_via_tcp(&self.current_deck_name, &cards) {
-                            Ok(bytes) => log::info!("Exported {} bytes", bytes),
-                            Err(e) => log::error!("Export failed: {}", e),
-                        }
-                    }
+                    self.export_current_deck();
+                    self.redraw();
+                }
+                'f' => {
+                    self.cycle_export_format();
+                    self.redraw();
+                }
+                't' => {
+                    self.cycle_export_timeout();
+                    self.redraw();
+                }
+                'c' => {
+                    self.toggle_export_encryption();
+                    self.redraw();
+                }
+                's' => {
+                    self.cycle_scheduler();
+                    self.redraw();
+                }
+                'b' => {
+                    self.cycle_leitner_boxes();
                     self.redraw();
                 }
                 'd' => {
@@ -560,25 +1008,612 @@ impl FlashcardApp {
         }
     }
 
-    fn do_import(&mut self) {
-        match import::listen_for_import() {
-            Some(result) => {
-                let name = result.name.unwrap_or_else(|| {
-                    format!("Imported {}", self.decks.len() + 1)
-                });
-                let name = self.unique_deck_name(&name);
-                self.storage.save_deck(&name, &result.cards);
-                log::info!("Imported deck '{}' with {} cards", name, result.cards.len());
+    fn open_deck(&mut self, name: String) {
+        let cards = match self.storage.load_deck(&name) {
+            Ok(cards) => cards,
+            Err(e) => {
+                self.report_error(e);
+                self.redraw();
+                return;
+            }
+        };
+        let today = scheduler::today();
+        let states = self.storage.load_states(&name, &cards);
+        self.settings = self.storage.load_settings(&name);
+        self.queue = (0..cards.len()).filter(|&i| states[i].is_due(today)).collect();
+        self.current_deck_name = name;
+        self.cards = cards;
+        self.states = states;
+        self.current_card = 0;
+        self.show_front();
+        self.reviewed = 0;
+        self.state = AppState::CardReview;
+        self.redraw();
+    }
+
+    /// Update the current card's schedule from a grade and move to the next card.
+    /// Missed cards are re-queued at the end of the session.
+    fn grade_current_card(&mut self, grade: Grade) {
+        let idx = match self.queue.get(self.current_card) {
+            Some(&idx) => idx,
+            None => return,
+        };
+        let sched = scheduler::scheduler_for(&self.settings);
+        let prev = self.states[idx];
+        self.states[idx] = sched.review(&prev, grade, scheduler::today());
+        if let Err(e) = self.storage.save_states(&self.current_deck_name, &self.cards, &self.states) {
+            self.report_error(e);
+        }
+        let entry = ReviewEntry {
+            card_id: self.cards[idx].id,
+            timestamp: scheduler::unix_time() as u32,
+            grade,
+            time_ms: self.shown_at.elapsed().as_millis().min(u32::MAX as u128) as u32,
+            prev_interval: prev.interval,
+            new_interval: self.states[idx].interval,
+        };
+        if let Err(e) = self.storage.append_review(&self.current_deck_name, &entry) {
+            self.report_error(e);
+        }
+        if grade == Grade::Again {
+            self.queue.push(idx);
+        }
+        self.reviewed += 1;
+        self.current_card += 1;
+        self.show_front();
+    }
+
+    /// Show the question side of the current card and start timing the answer.
+    fn show_front(&mut self) {
+        self.showing_back = false;
+        self.shown_at = Instant::now();
+    }
+
+    fn open_deck_menu(&mut self) {
+        if let Some(deck_meta) = self.decks.get(self.cursor) {
+            self.current_deck_name = deck_meta.name.clone();
+            self.settings = self.storage.load_settings(&self.current_deck_name);
+            self.review_count = self.storage.load_review_log(&self.current_deck_name).len();
+            self.state = AppState::DeckMenu { confirm_delete: false };
+        }
+    }
+
+    fn cycle_scheduler(&mut self) {
+        self.settings.scheduler = self.settings.scheduler.next();
+        if let Err(e) = self.storage.save_settings(&self.current_deck_name, &self.settings) {
+            self.report_error(e);
+        }
+    }
+
+    /// Step the Leitner box count through 3, 5 and 7 boxes with doubling intervals.
+    fn cycle_leitner_boxes(&mut self) {
+        if self.settings.scheduler != SchedulerKind::Leitner {
+            self.status.post(Severity::Warning, "Box count applies to Leitner only".to_string());
+            return;
+        }
+        let boxes = match self.settings.leitner_intervals.len() {
+            3 => 5,
+            5 => 7,
+            _ => 3,
+        };
+        self.settings.leitner_intervals = scheduler::default_leitner_intervals(boxes);
+        if let Err(e) = self.storage.save_settings(&self.current_deck_name, &self.settings) {
+            self.report_error(e);
+        }
+    }
+
+    fn start_import(&mut self) {
+        let existing = self.decks.iter().map(|d| d.name.clone()).collect();
+        match ImportListener::start(&self.trng, self.self_cid, AppOp::ImportDone.to_usize().unwrap(), existing) {
+            Ok(listener) => {
+                self.import_listener = Some(listener);
+                self.state = AppState::ImportWait;
+            }
+            Err(e) => self.report_error(e),
+        }
+    }
+
+    fn cancel_import(&mut self) {
+        if let Some(listener) = self.import_listener.take() {
+            listener.cancel();
+        }
+        self.status.post(Severity::Info, "Import cancelled".to_string());
+        self.state = AppState::DeckList;
+        self.refresh_deck_list();
+        self.redraw();
+    }
+
+    /// Called when the import listener thread reports back; the caller redraws.
+    /// A single deck goes to the preview screen, where nothing is saved yet;
+    /// a bundle of several is saved straight away. A cancelled listener's
+    /// thread may still report in; the current listener has no result then
+    /// and is kept.
+    fn finish_import(&mut self) {
+        let outcome = match self.import_listener.as_ref().and_then(|l| l.take_result()) {
+            Some(outcome) => outcome,
+            None => return,
+        };
+        self.import_listener = None;
+        match outcome {
+            Ok(Transfer::Decks(results)) => self.receive_decks(results),
+            Ok(Transfer::Encrypted(data)) => {
+                self.pending_encrypted = Some(data);
+                self.passphrase.clear();
+                self.state = AppState::Passphrase(PassphraseStep::Unlock);
+            }
+            Err(e) => {
+                self.report_error(e);
+                self.state = AppState::DeckList;
+                self.refresh_deck_list();
+            }
+        }
+    }
+
+    /// Preview a single received deck, or save a bundle of them.
+    fn receive_decks(&mut self, mut results: Vec<ImportResult>) {
+        if results.len() > 1 {
+            self.save_bundle(results);
+            return;
+        }
+        let result = results.remove(0);
+        self.pending_name = result.name.clone().unwrap_or_else(|| {
+            format!("Imported {}", self.decks.len() + 1)
+        });
+        self.pending_import = Some(result);
+        self.state = AppState::ImportPreview { renaming: false };
+    }
+
+    fn handle_key_passphrase(&mut self, key: char, step: PassphraseStep) {
+        if self.unlock_job.is_some() {
+            return;
+        }
+        match key {
+            '\r' | '\n' => self.submit_passphrase(step),
+            KEY_BACKSPACE => {
+                self.passphrase.pop();
+            }
+            c if is_text_key(c) => self.passphrase.push(c),
+            _ => return,
+        }
+        self.redraw();
+    }
+
+    fn submit_passphrase(&mut self, step: PassphraseStep) {
+        let passphrase = std::mem::take(&mut self.passphrase);
+        match step {
+            PassphraseStep::Export => {
+                if passphrase.chars().count() < crypto::MIN_PASSPHRASE_CHARS {
+                    let msg = format!("Use at least {} characters", crypto::MIN_PASSPHRASE_CHARS);
+                    self.status.post(Severity::Warning, msg);
+                    return;
+                }
+                self.first_passphrase = passphrase;
+                self.state = AppState::Passphrase(PassphraseStep::ConfirmExport);
+            }
+            PassphraseStep::ConfirmExport => {
+                let first = std::mem::take(&mut self.first_passphrase);
+                if passphrase != first {
+                    self.status.post(Severity::Warning, "Passphrases don't match".to_string());
+                    self.state = AppState::Passphrase(PassphraseStep::Export);
+                    return;
+                }
+                self.state = AppState::DeckMenu { confirm_delete: false };
+                self.send_export(Some(&passphrase));
+            }
+            PassphraseStep::Unlock => {
+                // Key derivation takes a few seconds on the device
+                if let Some(data) = self.pending_encrypted.take() {
+                    let done_op = AppOp::UnlockDone.to_usize().unwrap();
+                    self.unlock_job = Some(UnlockJob::start(data, passphrase, self.self_cid, done_op));
+                }
+            }
+        }
+    }
+
+    /// Called when the decryption thread reports back; the caller redraws.
+    /// A cancelled job's thread may still report in; the current job has no
+    /// result then and is kept.
+    fn finish_unlock(&mut self) {
+        let (outcome, data) = match self.unlock_job.as_ref().and_then(|job| job.take_result()) {
+            Some(finished) => finished,
+            None => return,
+        };
+        self.unlock_job = None;
+        match outcome {
+            Ok(results) => self.receive_decks(results),
+            Err(Error::Crypto(CryptoError::WrongPassphrase)) => {
+                // Let the user try again
+                self.status.post(Severity::Warning, CryptoError::WrongPassphrase.to_string());
+                self.pending_encrypted = Some(data);
+            }
+            Err(e) => {
+                self.report_error(e);
+                self.state = AppState::DeckList;
+                self.refresh_deck_list();
+            }
+        }
+    }
+
+    fn cancel_passphrase(&mut self, step: PassphraseStep) {
+        self.passphrase.clear();
+        self.first_passphrase.clear();
+        if step == PassphraseStep::Unlock {
+            // A decryption still running finishes unread
+            self.unlock_job = None;
+            self.pending_encrypted = None;
+            self.status.post(Severity::Info, "Import cancelled".to_string());
+            self.state = AppState::DeckList;
+        } else {
+            self.status.post(Severity::Info, "Export cancelled".to_string());
+            self.state = AppState::DeckMenu { confirm_delete: false };
+        }
+    }
+
+    /// Save every deck of a bundle: merged into the deck of the same name if
+    /// there is one, otherwise created with its settings and review state.
+    fn save_bundle(&mut self, results: Vec<ImportResult>) {
+        self.bundle_entries.clear();
+        let dropped = results.last().map_or(0, |r| r.report.decks_dropped);
+        for result in results {
+            let name = match &result.name {
+                Some(name) => name.clone(),
+                None => self.unique_deck_name(&format!("Imported {}", self.decks.len() + 1)),
+            };
+            let outcome = if result.cards.is_empty() {
+                BundleOutcome::Empty
+            } else if self.decks.iter().any(|d| d.name == name) {
+                match self.storage.merge_deck(&name, result.cards, false) {
+                    Ok(summary) => BundleOutcome::Merged(summary),
+                    Err(e) => BundleOutcome::Failed(e.to_string()),
+                }
+            } else {
+                match self.save_import(&name, &result) {
+                    Ok(()) => BundleOutcome::Created(result.cards.len()),
+                    Err(e) => BundleOutcome::Failed(e.to_string()),
+                }
+            };
+            log::info!("Bundle deck '{}': {:?}", name, outcome);
+            self.bundle_entries.push(BundleEntry { name, outcome });
+            // Later decks of the same name merge into this one
+            self.refresh_deck_list();
+        }
+        let failed = self.bundle_entries.iter().filter(|e| matches!(e.outcome, BundleOutcome::Failed(_))).count();
+        if failed > 0 {
+            self.status.post(Severity::Error, format!("{} of {} decks failed", failed, self.bundle_entries.len()));
+        } else if dropped > 0 {
+            self.status.post(Severity::Warning, format!("{} more decks over the limit not imported", dropped));
+        }
+        self.state = AppState::BundleSummary { scroll: 0 };
+    }
+
+    fn handle_key_bundle_summary(&mut self, key: char, scroll: usize) {
+        let last = self.bundle_entries.len().saturating_sub(ui::bundle_summary_lines(self.screensize));
+        self.state = match key {
+            '↓' | 'j' => AppState::BundleSummary { scroll: (scroll + 1).min(last) },
+            '↑' | 'k' => AppState::BundleSummary { scroll: scroll.saturating_sub(1) },
+            _ => AppState::DeckList,
+        };
+        self.redraw();
+    }
+
+    fn handle_key_import_preview(&mut self, key: char, renaming: bool) {
+        if renaming {
+            match key {
+                '\r' | '\n' => {
+                    let name = self.rename_buffer.trim();
+                    if !name.is_empty() {
+                        self.pending_name = name.to_string();
+                    }
+                    self.state = AppState::ImportPreview { renaming: false };
+                }
+                KEY_BACKSPACE => {
+                    self.rename_buffer.pop();
+                }
+                // Newlines would corrupt the deck index
+                c if is_text_key(c) => self.rename_buffer.push(c),
+                _ => return,
             }
-            None => {
-                log::info!("Import cancelled or failed");
+            self.redraw();
+            return;
+        }
+        match key {
+            'a' => self.accept_import(),
+            'r' => self.start_rename(),
+            'm' => self.merge_import(),
+            'x' => self.import_remove_missing = !self.import_remove_missing,
+            'd' | 'q' => self.discard_import(),
+            _ => return,
+        }
+        self.redraw();
+    }
+
+    fn pending_name_exists(&self) -> bool {
+        self.decks.iter().any(|d| d.name == self.pending_name)
+    }
+
+    /// Options and warnings shown under the preview's card list.
+    fn import_preview_details(&self, result: &ImportResult, renaming: bool) -> String {
+        let mut details = format!("{} cards, {} lines skipped", result.cards.len(), result.report.skipped());
+        if let Some(issue) = result.report.issues.first() {
+            details.push_str(&format!("\nline {}: {}", issue.line, issue.reason));
+        }
+        if result.report.over_limit > 0 {
+            details.push_str(&format!("\n{} cards over the limit dropped", result.report.over_limit));
+        }
+        if let Some(line) = result.report.too_large_at {
+            details.push_str(&format!("\nDeck too large, stopped at line {}", line));
+        }
+        if result.settings.is_some() || result.states.is_some() {
+            details.push_str("\nIncludes settings and review state");
+        }
+        if renaming {
+            details.push_str("\n\nType a name, Enter=ok F4=cancel");
+        } else if self.pending_name_exists() {
+            let on_off = if self.import_remove_missing { "on" } else { "off" };
+            details.push_str(&format!(
+                "\n\nA deck with this name exists.\nm=merge (x=remove missing: {})\na=save as '{}'",
+                on_off,
+                self.unique_deck_name(&self.pending_name)
+            ));
+        }
+        details
+    }
+
+    /// Save the pending import as a new deck, suffixing the name if taken.
+    fn accept_import(&mut self) {
+        let result = match self.pending_import.take() {
+            Some(result) => result,
+            None => return,
+        };
+        let name = self.unique_deck_name(&self.pending_name);
+        match self.save_import(&name, &result) {
+            Ok(()) => {
+                log::info!("Imported deck '{}' with {} cards", name, result.cards.len());
+                self.status.post(Severity::Info, format!("Imported '{}' ({} cards)", name, result.cards.len()));
             }
+            Err(e) => self.report_error(e),
         }
         self.state = AppState::DeckList;
         self.refresh_deck_list();
+    }
+
+    /// Save an imported deck, with its settings and review state if the
+    /// format carried them.
+    fn save_import(&self, name: &str, result: &ImportResult) -> Result<(), Error> {
+        self.storage.save_deck(name, &result.cards)?;
+        if let Some(settings) = &result.settings {
+            self.storage.save_settings(name, settings)?;
+        }
+        if let Some(states) = &result.states {
+            self.storage.save_states(name, &result.cards, states)?;
+        }
+        Ok(())
+    }
+
+    fn start_rename(&mut self) {
+        self.rename_buffer = self.pending_name.clone();
+        self.state = AppState::ImportPreview { renaming: true };
+    }
+
+    /// Merge the pending import into the deck of the same name.
+    fn merge_import(&mut self) {
+        if !self.pending_name_exists() {
+            self.status.post(Severity::Warning, "No deck with this name to merge into".to_string());
+            return;
+        }
+        let result = match self.pending_import.take() {
+            Some(result) => result,
+            None => return,
+        };
+        let name = self.pending_name.clone();
+        match self.storage.merge_deck(&name, result.cards, self.import_remove_missing) {
+            Ok(summary) => {
+                log::info!("Merged import into '{}': {:?}", name, summary);
+                self.current_deck_name = name;
+                self.state = AppState::ImportSummary(summary);
+            }
+            Err(e) => {
+                self.report_error(e);
+                self.state = AppState::DeckList;
+            }
+        }
+        self.refresh_deck_list();
+    }
+
+    fn discard_import(&mut self) {
+        self.pending_import = None;
+        self.status.post(Severity::Info, "Import discarded".to_string());
+        self.state = AppState::DeckList;
+    }
+
+    /// Export the current deck, first asking for a passphrase if exports are
+    /// encrypted.
+    fn export_current_deck(&mut self) {
+        if self.export_settings.encrypt {
+            self.passphrase.clear();
+            self.state = AppState::Passphrase(PassphraseStep::Export);
+            return;
+        }
+        self.send_export(None);
+    }
+
+    /// Send the current deck, sealed with `passphrase` if there is one. The
+    /// sealing happens on the export thread.
+    fn send_export(&mut self, passphrase: Option<&str>) {
+        let cards = match self.storage.load_deck(&self.current_deck_name) {
+            Ok(cards) => cards,
+            Err(e) => {
+                self.report_error(e);
+                return;
+            }
+        };
+        let name = &self.current_deck_name;
+        let settings = self.storage.load_settings(name);
+        let states = self.storage.load_states(name, &cards);
+        let payload = import::export_text(self.export_settings.format, name, &cards, &settings, &states).into_bytes();
+        let sealer = match passphrase.map(|p| Sealer::new(p, &self.trng)).transpose() {
+            Ok(sealer) => sealer,
+            Err(e) => {
+                self.report_error(e);
+                return;
+            }
+        };
+        let timeout = Duration::from_secs(self.export_settings.timeout_secs as u64);
+        self.export_job = Some(ExportJob::start(
+            payload,
+            sealer,
+            timeout,
+            self.self_cid,
+            AppOp::ExportUpdate.to_usize().unwrap(),
+        ));
+        self.state = AppState::ExportWait { backup: false };
+    }
+
+    /// Send the whole library as one backup archive, using the export port
+    /// and timeout.
+    fn start_backup(&mut self) {
+        let backup = match self.storage.backup() {
+            Ok(backup) => backup,
+            Err(e) => {
+                self.report_error(e);
+                return;
+            }
+        };
+        let timeout = Duration::from_secs(self.export_settings.timeout_secs as u64);
+        self.export_job = Some(ExportJob::start(
+            backup.encode(),
+            None,
+            timeout,
+            self.self_cid,
+            AppOp::ExportUpdate.to_usize().unwrap(),
+        ));
+        self.state = AppState::ExportWait { backup: true };
+    }
+
+    fn cancel_export(&mut self) {
+        if let Some(job) = self.export_job.take() {
+            job.cancel();
+        }
+        self.status.post(Severity::Info, "Export cancelled".to_string());
+        self.state = self.export_return_state();
         self.redraw();
     }
 
+    /// Where to go once an export ends: the deck menu, or the deck list for a backup.
+    fn export_return_state(&self) -> AppState {
+        match self.state {
+            AppState::ExportWait { backup: true } => AppState::DeckList,
+            _ => AppState::DeckMenu { confirm_delete: false },
+        }
+    }
+
+    /// Called when the export thread reports progress or finishes; the caller redraws.
+    fn update_export(&mut self) {
+        let outcome = match self.export_job.as_ref().and_then(|job| job.take_result()) {
+            Some(outcome) => outcome,
+            None => return,
+        };
+        self.export_job = None;
+        match outcome {
+            Ok(bytes) => self.status.post(Severity::Info, format!("Exported {} bytes", bytes)),
+            Err(e) => self.report_error(e),
+        }
+        self.state = self.export_return_state();
+    }
+
+    fn start_restore(&mut self) {
+        match ImportListener::start_restore(&self.trng, self.self_cid, AppOp::RestoreDone.to_usize().unwrap()) {
+            Ok(listener) => {
+                self.restore_listener = Some(listener);
+                self.state = AppState::RestoreWait;
+            }
+            Err(e) => self.report_error(e),
+        }
+    }
+
+    fn cancel_restore(&mut self) {
+        if let Some(listener) = self.restore_listener.take() {
+            listener.cancel();
+        }
+        self.status.post(Severity::Info, "Restore cancelled".to_string());
+        self.state = AppState::DeckList;
+        self.redraw();
+    }
+
+    /// Called when the restore listener reports back; the caller redraws.
+    /// A backup that passed its checks waits for confirmation. As with
+    /// imports, a report from a cancelled listener leaves the current one.
+    fn finish_restore(&mut self) {
+        let outcome = match self.restore_listener.as_ref().and_then(|l| l.take_result()) {
+            Some(outcome) => outcome,
+            None => return,
+        };
+        self.restore_listener = None;
+        match outcome {
+            Ok(received) => {
+                self.pending_restore = Some(received);
+                self.state = AppState::RestoreConfirm;
+            }
+            Err(e) => {
+                self.report_error(e);
+                self.state = AppState::DeckList;
+            }
+        }
+    }
+
+    /// Replace the library with the received backup.
+    fn confirm_restore(&mut self) {
+        let (backup, summary) = match self.pending_restore.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        match self.storage.restore(&backup) {
+            Ok(()) => {
+                log::info!("Restored {:?}", summary);
+                self.status.post(Severity::Info, format!("Restored {} decks", summary.decks));
+            }
+            Err(e) => self.report_error(e),
+        }
+        self.export_settings = self.storage.load_export_settings();
+        self.cursor = 0;
+        self.scroll_offset = 0;
+        self.state = AppState::DeckList;
+        self.refresh_deck_list();
+    }
+
+    fn discard_restore(&mut self) {
+        self.pending_restore = None;
+        self.status.post(Severity::Info, "Restore cancelled".to_string());
+        self.state = AppState::DeckList;
+    }
+
+    fn cycle_export_format(&mut self) {
+        self.export_settings.format = self.export_settings.format.next();
+        if let Err(e) = self.storage.save_export_settings(&self.export_settings) {
+            self.report_error(e);
+        }
+    }
+
+    fn cycle_export_timeout(&mut self) {
+        self.export_settings.cycle_timeout();
+        if let Err(e) = self.storage.save_export_settings(&self.export_settings) {
+            self.report_error(e);
+        }
+    }
+
+    fn toggle_export_encryption(&mut self) {
+        self.export_settings.encrypt = !self.export_settings.encrypt;
+        if let Err(e) = self.storage.save_export_settings(&self.export_settings) {
+            self.report_error(e);
+        }
+    }
+
+    /// Log an error and show it in the status bar.
+    fn report_error(&mut self, e: Error) {
+        log::error!("{}", e);
+        self.status.post(Severity::Error, e.to_string());
+    }
+
     fn refresh_deck_list(&mut self) {
         self.decks = self.storage.list_decks();
     }
@@ -597,9 +1632,9 @@ impl FlashcardApp {
     }
 
     fn shuffle_cards(&mut self) {
-        // Simple Fisher-Yates shuffle using system time as seed
+        // Simple Fisher-Yates shuffle of the session queue using system time as seed
         let seed = xous::create_server_id().unwrap().0[0] as usize;
-        let len = self.cards.len();
+        let len = self.queue.len();
         if len <= 1 {
             return;
         }
@@ -608,7 +1643,7 @@ impl FlashcardApp {
             // Simple LCG-based random
             rng = rng.wrapping_mul(1103515245).wrapping_add(12345);
             let j = rng % (i + 1);
-            self.cards.swap(i, j);
+            self.queue.swap(i, j);
         }
         log::info!("Shuffled {} cards", len);
     }
@@ -677,6 +1712,39 @@ fn main() -> ! {
                     }
                 }
             }),
+            Some(AppOp::ImportDone) => {
+                app.finish_import();
+                if allow_redraw {
+                    app.redraw();
+                }
+            }
+            Some(AppOp::RestoreDone) => {
+                app.finish_restore();
+                if allow_redraw {
+                    app.redraw();
+                }
+            }
+            Some(AppOp::ExportUpdate) => {
+                app.update_export();
+                let exporting = matches!(
+                    app.state,
+                    AppState::ExportWait { .. } | AppState::DeckMenu { .. } | AppState::DeckList
+                );
+                if allow_redraw && exporting {
+                    app.redraw();
+                }
+            }
+            Some(AppOp::UnlockDone) => {
+                app.finish_unlock();
+                if allow_redraw {
+                    app.redraw();
+                }
+            }
+            Some(AppOp::StatusExpire) => xous::msg_scalar_unpack!(msg, generation, _, _, _, {
+                if app.status.expire(generation) && allow_redraw {
+                    app.redraw();
+                }
+            }),
             Some(AppOp::Quit) => break,
             _ => log::error!("unknown opcode: {:?}", msg),
         }
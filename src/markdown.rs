@@ -0,0 +1,228 @@
+//! Markdown notes as decks.
+//!
+//! The first `#` heading names the deck. Every other heading is a card
+//! front, and the text under it, up to the next heading, is the back:
+//!
+//! ```text
+//! # Networking
+//! ## What does TCP add over IP?
+//! Ordered, reliable delivery with flow control.
+//! ## OSI layers
+//! - Physical
+//! - Data link
+//! ```
+//!
+//! `Q:` and `A:` lines make cards too, each running until the next `Q:`,
+//! heading or blank line. Inside code fences, `#` lines are content, not
+//! headings. `**` emphasis and inline code backticks are dropped, since the
+//! display shows plain text.
+
+use crate::import::{CardCollector, ImportResult, SkipReason};
+
+/// True if the text has Markdown card structure: a heading below the deck
+/// name or a `Q:` line, and no tab-separated card lines.
+pub fn is_markdown(data: &str) -> bool {
+    let mut in_fence = false;
+    let mut seen_title = false;
+    let mut has_cards = false;
+    let mut checked_first_line = false;
+    for line in data.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence || line.trim().is_empty() {
+            continue;
+        }
+        match heading(line) {
+            Some((1, _)) if !seen_title => seen_title = true,
+            Some(_) => has_cards = true,
+            None if line.starts_with('#') => {}
+            None => {
+                if !checked_first_line && line.contains('\t') {
+                    return false;
+                }
+                checked_first_line = true;
+                has_cards |= question(line).is_some();
+            }
+        }
+    }
+    has_cards
+}
+
+/// Parse Markdown notes into cards.
+pub fn parse_markdown(data: &str) -> ImportResult {
+    let mut name = None;
+    let mut collector = CardCollector::new();
+    let mut card: Option<Pending> = None;
+    let mut in_fence = false;
+
+    for (i, line) in data.lines().enumerate() {
+        let line_no = i + 1;
+        if is_fence(line) {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            if let Some(card) = &mut card {
+                card.push(line);
+            }
+            continue;
+        }
+        if let Some((level, text)) = heading(line) {
+            finish(&mut collector, card.take());
+            if level == 1 && name.is_none() {
+                name = Some(plain_text(text));
+            } else {
+                card = Some(Pending::new(line_no, text, Style::Heading));
+            }
+            continue;
+        }
+        if let Some(text) = question(line) {
+            finish(&mut collector, card.take());
+            card = Some(Pending::new(line_no, text, Style::Question));
+            continue;
+        }
+        let pending = match &mut card {
+            Some(pending) => pending,
+            None => continue,
+        };
+        if pending.style == Style::Question {
+            if let Some(text) = answer(line) {
+                pending.answered = true;
+                pending.push(text);
+                continue;
+            }
+            if line.trim().is_empty() && pending.answered {
+                finish(&mut collector, card.take());
+                continue;
+            }
+            if !pending.answered {
+                // The question continues onto this line
+                if !line.trim().is_empty() {
+                    pending.front.push('\n');
+                    pending.front.push_str(line.trim());
+                }
+                continue;
+            }
+        }
+        pending.push(line);
+    }
+    finish(&mut collector, card);
+    collector.finish(name)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Heading,
+    Question,
+}
+
+/// A card whose back is still being read.
+struct Pending {
+    line: usize,
+    front: String,
+    back: Vec<String>,
+    style: Style,
+    /// For `Q:` cards, whether the `A:` line has been seen.
+    answered: bool,
+}
+
+impl Pending {
+    fn new(line: usize, front: &str, style: Style) -> Self {
+        Self { line, front: front.trim().to_string(), back: Vec::new(), style, answered: false }
+    }
+
+    fn push(&mut self, line: &str) {
+        self.back.push(line.trim_end().to_string());
+    }
+}
+
+fn finish(collector: &mut CardCollector, card: Option<Pending>) {
+    let card = match card {
+        Some(card) => card,
+        None => return,
+    };
+    if card.style == Style::Question && !card.answered {
+        collector.skip(card.line, SkipReason::MissingAnswer);
+        return;
+    }
+    // Drop blank lines around the back and collapse runs of them inside it
+    let mut back = String::new();
+    let mut blank = false;
+    for line in &card.back {
+        if line.trim().is_empty() {
+            blank = !back.is_empty();
+            continue;
+        }
+        if blank {
+            back.push('\n');
+            blank = false;
+        }
+        if !back.is_empty() {
+            back.push('\n');
+        }
+        back.push_str(line);
+    }
+    collector.add(card.line, &plain_text(&card.front), &plain_text(&back), 0, Vec::new());
+}
+
+/// `#` to `######` followed by a space, with any closing `#`s removed.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let text = line[level..].strip_prefix(' ')?;
+    Some((level, text.trim().trim_end_matches('#').trim_end()))
+}
+
+fn question(line: &str) -> Option<&str> {
+    line.trim_start().strip_prefix("Q:")
+}
+
+fn answer(line: &str) -> Option<&str> {
+    line.trim_start().strip_prefix("A:").map(str::trim)
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+/// Drop `**` emphasis and inline code backticks.
+fn plain_text(text: &str) -> String {
+    text.replace("**", "").replace('`', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::ParseIssue;
+
+    #[test]
+    fn markdown_notes_parse() {
+        let data = "# Networking\n\n## What does **TCP** add?\nOrdered, reliable delivery.\n\n\
+                    ## OSI layers\n- Physical\n- Data link\n\n```\n# not a heading\n```\n\
+                    ## Empty\n\nQ: Port for\nimport?\n\nA: 7878\nover TCP\n\nQ: Unanswered\n";
+        assert!(is_markdown(data));
+        let result = parse_markdown(data);
+        assert_eq!(result.name.as_deref(), Some("Networking"));
+        let cards: Vec<(&str, &str)> = result.cards.iter().map(|c| (c.front.as_str(), c.back.as_str())).collect();
+        assert_eq!(
+            cards,
+            vec![
+                ("What does TCP add?", "Ordered, reliable delivery."),
+                ("OSI layers", "- Physical\n- Data link\n\n# not a heading"),
+                ("Port for\nimport?", "7878\nover TCP"),
+            ]
+        );
+        assert_eq!(
+            result.report.issues,
+            vec![
+                ParseIssue { line: 13, reason: SkipReason::EmptyBack },
+                ParseIssue { line: 21, reason: SkipReason::MissingAnswer },
+            ]
+        );
+        assert!(!is_markdown("# comment\nfront\tback\n## not a card\n"));
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use crate::deck::{Card, assign_ids};
+use crate::import::MAX_CARDS;
+
+/// What merging an import into an existing deck changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    /// New cards left out because the deck reached `MAX_CARDS`.
+    pub over_limit: usize,
+}
+
+pub struct MergeResult {
+    pub cards: Vec<Card>,
+    /// Ids of cards whose text changed; their review state should be reset.
+    pub updated_ids: Vec<u64>,
+    pub summary: MergeSummary,
+}
+
+/// Merge imported cards into an existing deck.
+///
+/// Each incoming card is matched to an existing card by id, or failing that
+/// by identical front text. Matched cards keep the existing id (and so their
+/// review state) and take the incoming text, and its tags if it has any;
+/// unmatched incoming cards are appended, as far as `MAX_CARDS` allows.
+/// Existing cards with no match are kept unless `remove_missing`.
+pub fn merge_cards(existing: &[Card], incoming: Vec<Card>, remove_missing: bool) -> MergeResult {
+    let mut cards = existing.to_vec();
+    let mut matched = vec![false; existing.len()];
+    let by_id: BTreeMap<u64, usize> = existing.iter().enumerate().map(|(i, c)| (c.id, i)).collect();
+    let mut by_front: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, card) in existing.iter().enumerate() {
+        by_front.entry(card.front.as_str()).or_default().push(i);
+    }
+
+    let mut summary = MergeSummary::default();
+    let mut updated_ids = Vec::new();
+    let mut added = Vec::new();
+    for card in incoming {
+        let found = by_id
+            .get(&card.id)
+            .copied()
+            .filter(|&i| !matched[i])
+            .or_else(|| {
+                by_front
+                    .get(card.front.as_str())
+                    .and_then(|indices| indices.iter().copied().find(|&i| !matched[i]))
+            });
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                let target = &mut cards[i];
+                if !card.tags.is_empty() {
+                    target.tags = card.tags;
+                }
+                if target.front == card.front && target.back == card.back {
+                    summary.unchanged += 1;
+                } else {
+                    target.front = card.front;
+                    target.back = card.back;
+                    updated_ids.push(target.id);
+                    summary.updated += 1;
+                }
+            }
+            None => added.push(card),
+        }
+    }
+
+    if remove_missing {
+        let before = cards.len();
+        let mut keep = matched.iter();
+        cards.retain(|_| *keep.next().unwrap());
+        summary.removed = before - cards.len();
+    }
+    let room = MAX_CARDS.saturating_sub(cards.len());
+    summary.over_limit = added.len().saturating_sub(room);
+    added.truncate(room);
+    summary.added = added.len();
+    cards.extend(added);
+    // New cards keep their ids unless one is already taken by an existing card
+    assign_ids(&mut cards);
+
+    MergeResult { cards, updated_ids, summary }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(id: u64, front: &str, back: &str) -> Card {
+        Card { id, ..Card::new(front, back) }
+    }
+
+    fn deck() -> Vec<Card> {
+        vec![card(1, "hola", "hello"), card(2, "adiós", "goodbye"), card(3, "gracias", "thanks")]
+    }
+
+    #[test]
+    fn unchanged_reimport_changes_nothing() {
+        let result = merge_cards(&deck(), deck(), true);
+        assert_eq!(result.summary, MergeSummary { unchanged: 3, ..Default::default() });
+        assert!(result.updated_ids.is_empty());
+        let ids: Vec<u64> = result.cards.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn matches_by_id_then_by_front() {
+        let incoming = vec![
+            // Same id, edited front: matched by id
+            card(1, "¡hola!", "hello"),
+            // Unknown id, same front, edited back: matched by front
+            card(99, "gracias", "thank you"),
+            // Id 0 as from a file without ids: matched by front
+            card(0, "adiós", "goodbye"),
+        ];
+        let result = merge_cards(&deck(), incoming, false);
+        assert_eq!(result.summary, MergeSummary { updated: 2, unchanged: 1, ..Default::default() });
+        // Matched cards keep their ids, and so their review state
+        assert_eq!(result.updated_ids, [1, 3]);
+        let cards: Vec<(u64, &str, &str)> =
+            result.cards.iter().map(|c| (c.id, c.front.as_str(), c.back.as_str())).collect();
+        assert_eq!(cards, [(1, "¡hola!", "hello"), (2, "adiós", "goodbye"), (3, "gracias", "thank you")]);
+    }
+
+    #[test]
+    fn new_cards_are_appended_with_unique_ids() {
+        // The second card's id was already matched by the first
+        let incoming = vec![card(1, "hola", "hello"), card(1, "perro", "dog")];
+        let result = merge_cards(&deck(), incoming, false);
+        assert_eq!(result.summary, MergeSummary { added: 1, unchanged: 1, ..Default::default() });
+        assert_eq!(result.cards.len(), 4);
+        assert_eq!(result.cards[0].id, 1);
+        assert_eq!(result.cards[3].front, "perro");
+        assert!(![0, 1, 2, 3].contains(&result.cards[3].id));
+    }
+
+    #[test]
+    fn duplicate_fronts_match_one_to_one() {
+        let existing = vec![card(1, "bank", "river side"), card(2, "bank", "money")];
+        let incoming = vec![card(0, "bank", "river side"), card(0, "bank", "vault"), card(0, "bank", "tilt")];
+        let result = merge_cards(&existing, incoming, false);
+        assert_eq!(result.summary, MergeSummary { added: 1, updated: 1, unchanged: 1, removed: 0, over_limit: 0 });
+        assert_eq!(result.updated_ids, [2]);
+        let backs: Vec<&str> = result.cards.iter().map(|c| c.back.as_str()).collect();
+        assert_eq!(backs, ["river side", "vault", "tilt"]);
+    }
+
+    #[test]
+    fn missing_cards_are_kept_unless_removed() {
+        let incoming = vec![card(2, "adiós", "goodbye")];
+        let kept = merge_cards(&deck(), incoming.clone(), false);
+        assert_eq!(kept.summary, MergeSummary { unchanged: 1, ..Default::default() });
+        assert_eq!(kept.cards.len(), 3);
+
+        let removed = merge_cards(&deck(), incoming, true);
+        assert_eq!(removed.summary, MergeSummary { unchanged: 1, removed: 2, ..Default::default() });
+        assert_eq!(removed.cards.len(), 1);
+        assert_eq!(removed.cards[0].id, 2);
+    }
+
+    #[test]
+    fn tags_are_replaced_only_when_given() {
+        let mut existing = deck();
+        existing[0].tags = vec!["greeting".to_string()];
+        existing[1].tags = vec!["old".to_string()];
+        let mut tagged = card(2, "adiós", "goodbye");
+        tagged.tags = vec!["farewell".to_string()];
+        let result = merge_cards(&existing, vec![card(1, "hola", "hello"), tagged], false);
+        assert_eq!(result.cards[0].tags, ["greeting"]);
+        assert_eq!(result.cards[1].tags, ["farewell"]);
+        // A tag change alone doesn't reset review state
+        assert_eq!(result.summary.unchanged, 2);
+    }
+
+    #[test]
+    fn merges_stop_at_the_card_limit() {
+        let incoming: Vec<Card> = (0..MAX_CARDS).map(|i| card(0, &format!("new {}", i), "x")).collect();
+        let result = merge_cards(&deck(), incoming.clone(), false);
+        assert_eq!(result.cards.len(), MAX_CARDS);
+        assert_eq!((result.summary.added, result.summary.over_limit), (MAX_CARDS - 3, 3));
+        assert_eq!(result.cards.last().unwrap().front, format!("new {}", MAX_CARDS - 4));
+
+        // Removing missing cards first makes room for the new ones
+        let result = merge_cards(&deck(), incoming, true);
+        assert_eq!((result.summary.added, result.summary.removed, result.summary.over_limit), (MAX_CARDS, 3, 0));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::scheduler::Grade;
+
+const LOG_VERSION: u8 = 1;
+pub const ENTRY_LEN: usize = 25;
+
+/// One answered card, as recorded in a deck's append-only review log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReviewEntry {
+    pub card_id: u64,
+    /// Unix time of the answer, in seconds.
+    pub timestamp: u32,
+    pub grade: Grade,
+    /// Time from showing the question to grading, in milliseconds.
+    pub time_ms: u32,
+    /// Interval in days before and after the review.
+    pub prev_interval: u32,
+    pub new_interval: u32,
+}
+
+/// Header written once when a log key is created: [u8: version]
+pub fn log_header() -> [u8; 1] {
+    [LOG_VERSION]
+}
+
+/// Serialize one log entry (fixed size, little endian):
+/// [u64: card_id][u32: timestamp][u8: grade][u32: time_ms][u32: prev_interval][u32: new_interval]
+pub fn serialize_entry(entry: &ReviewEntry) -> [u8; ENTRY_LEN] {
+    let mut buf = [0u8; ENTRY_LEN];
+    buf[0..8].copy_from_slice(&entry.card_id.to_le_bytes());
+    buf[8..12].copy_from_slice(&entry.timestamp.to_le_bytes());
+    buf[12] = entry.grade.to_u8();
+    buf[13..17].copy_from_slice(&entry.time_ms.to_le_bytes());
+    buf[17..21].copy_from_slice(&entry.prev_interval.to_le_bytes());
+    buf[21..25].copy_from_slice(&entry.new_interval.to_le_bytes());
+    buf
+}
+
+/// Deserialize a whole review log, header included.
+/// A partially written trailing entry is ignored, and so is an entry with an
+/// unknown grade; entries are fixed size, so the rest still line up.
+pub fn deserialize_log(data: &[u8]) -> Option<Vec<ReviewEntry>> {
+    if data.is_empty() || data[0] != LOG_VERSION {
+        return None;
+    }
+    let u32_at = |c: &[u8], p: usize| u32::from_le_bytes([c[p], c[p + 1], c[p + 2], c[p + 3]]);
+    let mut entries = Vec::with_capacity((data.len() - 1) / ENTRY_LEN);
+    for c in data[1..].chunks_exact(ENTRY_LEN) {
+        let grade = match Grade::from_u8(c[12]) {
+            Some(grade) => grade,
+            None => continue,
+        };
+        let mut id = [0u8; 8];
+        id.copy_from_slice(&c[0..8]);
+        entries.push(ReviewEntry {
+            card_id: u64::from_le_bytes(id),
+            timestamp: u32_at(c, 8),
+            grade,
+            time_ms: u32_at(c, 13),
+            prev_interval: u32_at(c, 17),
+            new_interval: u32_at(c, 21),
+        });
+    }
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(card_id: u64, grade: Grade) -> ReviewEntry {
+        ReviewEntry { card_id, timestamp: 1_760_000_000, grade, time_ms: 4200, prev_interval: 6, new_interval: 15 }
+    }
+
+    fn log(entries: &[ReviewEntry]) -> Vec<u8> {
+        let mut data = log_header().to_vec();
+        for entry in entries {
+            data.extend_from_slice(&serialize_entry(entry));
+        }
+        data
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let entries = [entry(1, Grade::Good), entry(u64::MAX, Grade::Again), entry(7, Grade::Easy)];
+        let data = log(&entries);
+        assert_eq!(data.len(), 1 + 3 * ENTRY_LEN);
+        assert_eq!(deserialize_log(&data), Some(entries.to_vec()));
+        assert_eq!(deserialize_log(&log_header()), Some(Vec::new()));
+        assert_eq!(deserialize_log(&[]), None);
+        assert_eq!(deserialize_log(&[9]), None);
+    }
+
+    #[test]
+    fn partial_trailing_entry_is_ignored() {
+        let mut data = log(&[entry(1, Grade::Good), entry(2, Grade::Hard)]);
+        data.truncate(data.len() - 10);
+        assert_eq!(deserialize_log(&data), Some(vec![entry(1, Grade::Good)]));
+    }
+
+    #[test]
+    fn bad_entry_keeps_the_rest_of_the_history() {
+        let mut data = log(&[entry(1, Grade::Good), entry(2, Grade::Hard), entry(3, Grade::Easy)]);
+        data[1 + ENTRY_LEN + 12] = 0xff;
+        assert_eq!(deserialize_log(&data), Some(vec![entry(1, Grade::Good), entry(3, Grade::Easy)]));
+    }
+}
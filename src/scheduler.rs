@@ -0,0 +1,538 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fsrs::Fsrs;
+
+const SCHED_VERSION: u8 = 4;
+const SETTINGS_VERSION: u8 = 1;
+const RECORD_LEN_V1: usize = 14;
+const RECORD_LEN_V2: usize = 15;
+const RECORD_LEN_V3: usize = 23;
+const RECORD_LEN: usize = 31;
+
+/// Ease factors are stored in permille (2500 = 2.5) since the CPU has no FPU.
+const DEFAULT_EASE: u16 = 2500;
+const MIN_EASE: u16 = 1300;
+const MAX_INTERVAL: u32 = 36500;
+
+/// How well the user recalled a card, from the four grade keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grade {
+    Again = 0,
+    Hard = 1,
+    Good = 2,
+    Easy = 3,
+}
+
+impl Grade {
+    pub fn from_key(key: char) -> Option<Grade> {
+        match key {
+            '1' => Some(Grade::Again),
+            '2' => Some(Grade::Hard),
+            '3' => Some(Grade::Good),
+            '4' => Some(Grade::Easy),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(v: u8) -> Option<Grade> {
+        match v {
+            0 => Some(Grade::Again),
+            1 => Some(Grade::Hard),
+            2 => Some(Grade::Good),
+            3 => Some(Grade::Easy),
+            _ => None,
+        }
+    }
+
+    /// SM-2 response quality (0-5) for this grade.
+    fn quality(self) -> u16 {
+        match self {
+            Grade::Again => 2,
+            Grade::Hard => 3,
+            Grade::Good => 4,
+            Grade::Easy => 5,
+        }
+    }
+}
+
+/// Review state kept for every card in a deck.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CardState {
+    /// Ease factor in permille.
+    pub ease: u16,
+    /// Current interval in days.
+    pub interval: u32,
+    /// Consecutive successful reviews.
+    pub reps: u32,
+    /// Day number (days since Unix epoch) the card is next due.
+    pub due: u32,
+    /// Leitner box, starting at 1.
+    pub leitner_box: u8,
+    /// FSRS memory stability in days, Q16.16. Zero until the first FSRS review.
+    pub stability: u32,
+    /// FSRS difficulty (1-10), Q16.16.
+    pub difficulty: u32,
+}
+
+impl CardState {
+    /// A card that has never been reviewed; due immediately.
+    pub fn new() -> Self {
+        Self {
+            ease: DEFAULT_EASE,
+            interval: 0,
+            reps: 0,
+            due: 0,
+            leitner_box: 1,
+            stability: 0,
+            difficulty: 0,
+        }
+    }
+
+    pub fn is_due(&self, today: u32) -> bool {
+        self.due <= today
+    }
+}
+
+impl Default for CardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Current day number, used for due dates.
+pub fn today() -> u32 {
+    (unix_time() / 86_400) as u32
+}
+
+/// A scheduling algorithm: turns a grade into a card's next review state.
+pub trait Scheduler {
+    fn review(&self, state: &CardState, grade: Grade, today: u32) -> CardState;
+}
+
+/// Which scheduler a deck uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulerKind {
+    Sm2 = 0,
+    Leitner = 1,
+    Fsrs = 2,
+}
+
+impl SchedulerKind {
+    pub fn from_u8(v: u8) -> Option<SchedulerKind> {
+        match v {
+            0 => Some(SchedulerKind::Sm2),
+            1 => Some(SchedulerKind::Leitner),
+            2 => Some(SchedulerKind::Fsrs),
+            _ => None,
+        }
+    }
+
+    /// The next scheduler in the deck menu's cycle.
+    pub fn next(self) -> SchedulerKind {
+        match self {
+            SchedulerKind::Sm2 => SchedulerKind::Leitner,
+            SchedulerKind::Leitner => SchedulerKind::Fsrs,
+            SchedulerKind::Fsrs => SchedulerKind::Sm2,
+        }
+    }
+}
+
+/// Build the scheduler selected by a deck's settings.
+pub fn scheduler_for(settings: &DeckSettings) -> Box<dyn Scheduler> {
+    match settings.scheduler {
+        SchedulerKind::Sm2 => Box::new(Sm2),
+        SchedulerKind::Leitner => Box::new(Leitner::new(settings.leitner_intervals.clone())),
+        SchedulerKind::Fsrs => Box::new(Fsrs::new()),
+    }
+}
+
+pub struct Sm2;
+
+impl Scheduler for Sm2 {
+    fn review(&self, state: &CardState, grade: Grade, today: u32) -> CardState {
+        review_sm2(state, grade, today)
+    }
+}
+
+/// Leitner boxes: a correct answer moves a card up one box, a miss sends it
+/// back to box 1. Each box has a fixed review interval.
+pub struct Leitner {
+    intervals: Vec<u32>,
+}
+
+pub const DEFAULT_LEITNER_BOXES: usize = 5;
+pub const MAX_LEITNER_BOXES: usize = 10;
+
+impl Leitner {
+    pub fn new(intervals: Vec<u32>) -> Self {
+        if intervals.is_empty() {
+            return Self { intervals: default_leitner_intervals(DEFAULT_LEITNER_BOXES) };
+        }
+        Self { intervals }
+    }
+}
+
+/// Doubling intervals (1, 2, 4, ... days), one per box.
+pub fn default_leitner_intervals(boxes: usize) -> Vec<u32> {
+    (0..boxes.clamp(1, MAX_LEITNER_BOXES)).map(|i| 1 << i).collect()
+}
+
+impl Scheduler for Leitner {
+    fn review(&self, state: &CardState, grade: Grade, today: u32) -> CardState {
+        let boxes = self.intervals.len() as u8;
+        let (leitner_box, reps) = if grade == Grade::Again {
+            (1, 0)
+        } else {
+            ((state.leitner_box.max(1) + 1).min(boxes), state.reps + 1)
+        };
+        let interval = self.intervals[leitner_box as usize - 1];
+        CardState {
+            interval,
+            reps,
+            due: today + interval,
+            leitner_box,
+            ..*state
+        }
+    }
+}
+
+/// Per-deck scheduling settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeckSettings {
+    pub scheduler: SchedulerKind,
+    /// Review interval in days for each Leitner box; the length is the box count.
+    pub leitner_intervals: Vec<u32>,
+}
+
+impl DeckSettings {
+    pub fn new() -> Self {
+        Self {
+            scheduler: SchedulerKind::Sm2,
+            leitner_intervals: default_leitner_intervals(DEFAULT_LEITNER_BOXES),
+        }
+    }
+
+    /// Short description for the deck menu.
+    pub fn describe(&self) -> String {
+        match self.scheduler {
+            SchedulerKind::Sm2 => "SM-2".to_string(),
+            SchedulerKind::Fsrs => "FSRS".to_string(),
+            SchedulerKind::Leitner => {
+                let days: Vec<String> = self.leitner_intervals.iter().map(|d| d.to_string()).collect();
+                format!("Leitner, {} boxes ({}d)", self.leitner_intervals.len(), days.join("/"))
+            }
+        }
+    }
+}
+
+impl Default for DeckSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serialize deck settings to binary format:
+/// [u8: version][u8: scheduler][u8: box_count] { [u32: interval] } ...
+pub fn serialize_settings(settings: &DeckSettings) -> Vec<u8> {
+    let mut buf = vec![SETTINGS_VERSION, settings.scheduler as u8, settings.leitner_intervals.len() as u8];
+    for interval in &settings.leitner_intervals {
+        buf.extend_from_slice(&interval.to_le_bytes());
+    }
+    buf
+}
+
+/// Deserialize deck settings from binary format.
+pub fn deserialize_settings(data: &[u8]) -> Option<DeckSettings> {
+    if data.len() < 3 || data[0] != SETTINGS_VERSION {
+        return None;
+    }
+    let scheduler = SchedulerKind::from_u8(data[1])?;
+    let boxes = data[2] as usize;
+    if boxes == 0 || boxes > MAX_LEITNER_BOXES || data.len() < 3 + boxes * 4 {
+        return None;
+    }
+    let leitner_intervals = data[3..3 + boxes * 4]
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    Some(DeckSettings { scheduler, leitner_intervals })
+}
+
+/// Apply one SM-2 review to a card's state.
+pub fn review_sm2(state: &CardState, grade: Grade, today: u32) -> CardState {
+    let q = grade.quality();
+    // EF' = EF + (0.1 - (5 - q) * (0.08 + (5 - q) * 0.02)), in permille
+    let miss = 5 - q as i32;
+    let delta = 100 - miss * (80 + miss * 20);
+    let ease = (state.ease as i32 + delta).max(MIN_EASE as i32) as u16;
+
+    let (reps, interval) = if grade == Grade::Again {
+        (0, 1)
+    } else {
+        let reps = state.reps + 1;
+        let interval = match reps {
+            1 => 1,
+            2 => 6,
+            _ => {
+                let next = (state.interval as u64 * ease as u64 + 500) / 1000;
+                (next as u32).max(state.interval + 1)
+            }
+        };
+        (reps, interval.min(MAX_INTERVAL))
+    };
+
+    CardState {
+        ease,
+        interval,
+        reps,
+        due: today + interval,
+        ..*state
+    }
+}
+
+/// Serialize review state to binary format, one record per card id:
+/// [u8: version][u32: count]
+///   { [u64: card_id][u16: ease][u32: interval][u32: reps][u32: due][u8: box]
+///     [u32: stability][u32: difficulty] } ...
+pub fn serialize_states(ids: &[u64], states: &[CardState]) -> Vec<u8> {
+    let count = ids.len().min(states.len());
+    let mut buf = Vec::with_capacity(5 + count * RECORD_LEN);
+    buf.push(SCHED_VERSION);
+    buf.extend_from_slice(&(count as u32).to_le_bytes());
+    for (id, s) in ids.iter().zip(states) {
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&s.ease.to_le_bytes());
+        buf.extend_from_slice(&s.interval.to_le_bytes());
+        buf.extend_from_slice(&s.reps.to_le_bytes());
+        buf.extend_from_slice(&s.due.to_le_bytes());
+        buf.push(s.leitner_box);
+        buf.extend_from_slice(&s.stability.to_le_bytes());
+        buf.extend_from_slice(&s.difficulty.to_le_bytes());
+    }
+    buf
+}
+
+/// Deserialize review state from binary format, returning one state per
+/// entry in `ids`. Cards with no stored record get a fresh state.
+/// Versions before 4 have no ids and are matched to cards by position;
+/// they load with every card in Leitner box 1 (version 1) and no FSRS
+/// memory state (versions 1 and 2).
+pub fn deserialize_states(data: &[u8], ids: &[u64]) -> Option<Vec<CardState>> {
+    if data.len() < 5 {
+        return None;
+    }
+    let (record_len, keyed) = match data[0] {
+        1 => (RECORD_LEN_V1, false),
+        2 => (RECORD_LEN_V2, false),
+        3 => (RECORD_LEN_V3, false),
+        SCHED_VERSION => (RECORD_LEN, true),
+        _ => return None,
+    };
+    let count = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    // A corrupt count must not wrap the size on the 32-bit target
+    let needed = count.checked_mul(record_len).and_then(|n| n.checked_add(5))?;
+    if data.len() < needed {
+        return None;
+    }
+    if !keyed {
+        let mut states = deserialize_records(&data[5..], count, record_len);
+        states.resize(ids.len(), CardState::new());
+        return Some(states);
+    }
+    let mut by_id = BTreeMap::new();
+    for i in 0..count {
+        let p = 5 + i * RECORD_LEN;
+        let id = u64::from_le_bytes(data[p..p + 8].try_into().ok()?);
+        by_id.insert(id, p + 8);
+    }
+    let states = ids
+        .iter()
+        .map(|id| match by_id.get(id) {
+            Some(&p) => deserialize_records(&data[p..], 1, RECORD_LEN_V3)[0],
+            None => CardState::new(),
+        })
+        .collect();
+    Some(states)
+}
+
+/// Decode `count` state records of `record_len` bytes, without card ids.
+fn deserialize_records(data: &[u8], count: usize, record_len: usize) -> Vec<CardState> {
+    let u32_at = |p: usize| u32::from_le_bytes([data[p], data[p + 1], data[p + 2], data[p + 3]]);
+    let mut states = Vec::with_capacity(count);
+    for i in 0..count {
+        let p = i * record_len;
+        states.push(CardState {
+            ease: u16::from_le_bytes([data[p], data[p + 1]]),
+            interval: u32_at(p + 2),
+            reps: u32_at(p + 6),
+            due: u32_at(p + 10),
+            leitner_box: if record_len > RECORD_LEN_V1 { data[p + 14].max(1) } else { 1 },
+            stability: if record_len > RECORD_LEN_V2 { u32_at(p + 15) } else { 0 },
+            difficulty: if record_len > RECORD_LEN_V2 { u32_at(p + 19) } else { 0 },
+        });
+    }
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A v1 record: [u16: ease][u32: interval][u32: reps][u32: due]
+    fn v1_record(ease: u16, interval: u32, reps: u32, due: u32) -> Vec<u8> {
+        let mut buf = ease.to_le_bytes().to_vec();
+        for v in [interval, reps, due] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf
+    }
+
+    fn with_header(version: u8, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![version];
+        buf.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        for record in records {
+            buf.extend_from_slice(record);
+        }
+        buf
+    }
+
+    fn state(ease: u16, interval: u32, reps: u32, due: u32) -> CardState {
+        CardState { ease, interval, reps, due, ..CardState::new() }
+    }
+
+    #[test]
+    fn v1_states_load_in_box_one() {
+        let data = with_header(1, &[v1_record(2600, 6, 2, 20_000), v1_record(1300, 1, 0, 20_001)]);
+        assert_eq!(data.len(), 5 + 2 * RECORD_LEN_V1);
+        let states = deserialize_states(&data, &[11, 22, 33]).unwrap();
+        assert_eq!(states, vec![state(2600, 6, 2, 20_000), state(1300, 1, 0, 20_001), CardState::new()]);
+    }
+
+    #[test]
+    fn v2_states_keep_their_box() {
+        let mut record = v1_record(2500, 4, 3, 20_000);
+        record.push(3);
+        let mut empty_box = v1_record(2500, 1, 1, 20_001);
+        empty_box.push(0);
+        let states = deserialize_states(&with_header(2, &[record, empty_box]), &[11, 22]).unwrap();
+        assert_eq!(states[0], CardState { leitner_box: 3, ..state(2500, 4, 3, 20_000) });
+        // Box 0 was never valid; it loads as box 1
+        assert_eq!(states[1].leitner_box, 1);
+    }
+
+    #[test]
+    fn v3_states_keep_fsrs_memory() {
+        let mut record = v1_record(2500, 9, 4, 20_000);
+        record.push(2);
+        record.extend_from_slice(&(5u32 << 16).to_le_bytes());
+        record.extend_from_slice(&(7u32 << 16).to_le_bytes());
+        let states = deserialize_states(&with_header(3, &[record]), &[11]).unwrap();
+        let expected = state(2500, 9, 4, 20_000);
+        assert_eq!(states, vec![CardState { leitner_box: 2, stability: 5 << 16, difficulty: 7 << 16, ..expected }]);
+    }
+
+    #[test]
+    fn v4_states_follow_card_ids() {
+        let a = CardState { leitner_box: 2, stability: 3 << 16, difficulty: 6 << 16, ..state(2700, 12, 5, 20_010) };
+        let b = state(2400, 1, 1, 20_002);
+        let data = serialize_states(&[11, 22], &[a, b]);
+        assert_eq!(data.len(), 5 + 2 * RECORD_LEN);
+        // Reordered, with a new card and a deleted one
+        let states = deserialize_states(&data, &[22, 33, 11]).unwrap();
+        assert_eq!(states, vec![b, CardState::new(), a]);
+    }
+
+    #[test]
+    fn corrupt_states_are_refused() {
+        let data = serialize_states(&[11], &[CardState::new()]);
+        assert!(deserialize_states(&data[..data.len() - 1], &[11]).is_none());
+        assert!(deserialize_states(&[9, 0, 0, 0, 0], &[]).is_none());
+        assert!(deserialize_states(&[SCHED_VERSION, 0, 0], &[]).is_none());
+        for version in 1..=SCHED_VERSION {
+            let mut huge = vec![version];
+            huge.extend_from_slice(&u32::MAX.to_le_bytes());
+            huge.extend_from_slice(&[0; RECORD_LEN]);
+            assert!(deserialize_states(&huge, &[11]).is_none());
+        }
+    }
+
+    #[test]
+    fn sm2_intervals_grow_with_ease() {
+        let first = review_sm2(&CardState::new(), Grade::Good, 100);
+        assert_eq!(first, state(2500, 1, 1, 101));
+        let second = review_sm2(&first, Grade::Good, 101);
+        assert_eq!(second, state(2500, 6, 2, 107));
+        // 6 days at ease 2.5 rounds to 15
+        let third = review_sm2(&second, Grade::Good, 107);
+        assert_eq!(third, state(2500, 15, 3, 122));
+
+        let easy = review_sm2(&second, Grade::Easy, 107);
+        assert_eq!((easy.ease, easy.interval), (2600, 16));
+        let hard = review_sm2(&second, Grade::Hard, 107);
+        assert_eq!((hard.ease, hard.interval), (2360, 14));
+    }
+
+    #[test]
+    fn sm2_again_resets_the_card() {
+        let learned = state(2500, 40, 5, 200);
+        let missed = review_sm2(&learned, Grade::Again, 210);
+        assert_eq!(missed, state(2180, 1, 0, 211));
+        // Relearning starts the 1, 6, ... sequence over at the lower ease
+        let relearned = review_sm2(&review_sm2(&missed, Grade::Good, 211), Grade::Good, 212);
+        assert_eq!((relearned.interval, relearned.reps, relearned.due), (6, 2, 218));
+    }
+
+    #[test]
+    fn sm2_ease_and_interval_are_bounded() {
+        let missed = review_sm2(&state(1400, 3, 4, 0), Grade::Again, 10);
+        assert_eq!(missed.ease, MIN_EASE);
+        let floored = review_sm2(&state(MIN_EASE, 3, 4, 0), Grade::Hard, 10);
+        assert_eq!(floored.ease, MIN_EASE);
+        // A low ease still lengthens the interval by at least a day
+        let low = review_sm2(&state(MIN_EASE, 1, 2, 0), Grade::Good, 10);
+        assert_eq!((low.interval, low.due), (2, 12));
+        let long = review_sm2(&state(2500, 30_000, 9, 0), Grade::Good, 10);
+        assert_eq!((long.interval, long.due), (MAX_INTERVAL, 10 + MAX_INTERVAL));
+    }
+
+    fn in_box(leitner_box: u8) -> CardState {
+        CardState { leitner_box, reps: 2, ..CardState::new() }
+    }
+
+    #[test]
+    fn leitner_moves_one_box_at_a_time() {
+        let leitner = Leitner::new(default_leitner_intervals(5));
+        let up = leitner.review(&in_box(2), Grade::Good, 50);
+        assert_eq!((up.leitner_box, up.interval, up.reps, up.due), (3, 4, 3, 54));
+        // Hard and Easy promote by one box too
+        assert_eq!(leitner.review(&in_box(2), Grade::Hard, 50).leitner_box, 3);
+        assert_eq!(leitner.review(&in_box(2), Grade::Easy, 50).leitner_box, 3);
+
+        let missed = leitner.review(&in_box(4), Grade::Again, 50);
+        assert_eq!((missed.leitner_box, missed.interval, missed.reps, missed.due), (1, 1, 0, 51));
+    }
+
+    #[test]
+    fn leitner_stays_within_its_boxes() {
+        let leitner = Leitner::new(vec![1, 3, 9]);
+        let top = leitner.review(&in_box(3), Grade::Good, 50);
+        assert_eq!((top.leitner_box, top.interval), (3, 9));
+        // A card left in box 5 when the deck had more boxes lands in the last one
+        let shrunk = leitner.review(&in_box(5), Grade::Good, 50);
+        assert_eq!((shrunk.leitner_box, shrunk.interval, shrunk.due), (3, 9, 59));
+        assert_eq!(leitner.review(&in_box(5), Grade::Again, 50).leitner_box, 1);
+    }
+}
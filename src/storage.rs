@@ -1,9 +1,31 @@
 use std::io::{Read, Write, Seek, SeekFrom};
 
-use crate::deck::{Card, DeckMeta, deserialize_cards, serialize_cards};
+use crate::backup::{Backup, DECK_KEY_PREFIXES, INDEX_KEY, parse_index};
+use crate::deck::{
+    Card, DeckMeta, FORMAT_VERSION, FormatError, HEADER_LEN, assign_ids, deserialize_cards, format_version,
+    read_card_count, serialize_cards,
+};
+use crate::error::Error;
+use crate::import::{ExportSettings, deserialize_export_settings, serialize_export_settings};
+use crate::merge::{MergeSummary, merge_cards};
+use crate::review_log::{ReviewEntry, deserialize_log, log_header, serialize_entry};
+use crate::scheduler::{
+    CardState, DeckSettings, deserialize_settings, deserialize_states, serialize_settings, serialize_states, unix_time,
+};
 
 const DICT_NAME: &str = "flashcards";
-const INDEX_KEY: &str = "_index";
+const EXPORT_SETTINGS_KEY: &str = "_export";
+
+/// True if `name` can name a deck: not blank, and free of control
+/// characters, since the index keeps one name per line. Importers drop a
+/// name that fails this, so the deck falls back to a default one.
+pub fn valid_deck_name(name: &str) -> bool {
+    !name.trim().is_empty() && !name.contains(char::is_control)
+}
+
+fn check_deck_name(name: &str) -> Result<(), Error> {
+    if valid_deck_name(name) { Ok(()) } else { Err(Error::DeckName(name.to_string())) }
+}
 
 pub struct DeckStorage {
     pddb: pddb::Pddb,
@@ -28,55 +50,190 @@ impl DeckStorage {
     }
 
     /// Load a deck's cards by name.
-    pub fn load_deck(&self, name: &str) -> Option<Vec<Card>> {
-        let key_name = format!("deck.{}", name);
-        match self.pddb.get(DICT_NAME, &key_name, None, false, false, None, None::<fn()>) {
-            Ok(mut key) => {
-                let mut data = Vec::new();
-                key.seek(SeekFrom::Start(0)).ok();
-                if key.read_to_end(&mut data).is_ok() && !data.is_empty() {
-                    deserialize_cards(&data)
-                } else {
-                    None
-                }
+    /// Decks stored in an older format are rewritten in the current one.
+    pub fn load_deck(&self, name: &str) -> Result<Vec<Card>, Error> {
+        let data = self.read_key(&format!("deck.{}", name))?;
+        if data.is_empty() {
+            return Err(Error::Format(FormatError::Corrupt));
+        }
+        let cards = deserialize_cards(&data)?;
+        let version = format_version(&data);
+        if version < FORMAT_VERSION {
+            log::info!("Migrating deck '{}' from format {} to {}", name, version, FORMAT_VERSION);
+            // The deck is still usable in memory if rewriting it fails
+            let migrated = serialize_cards(&cards)
+                .map_err(Error::from)
+                .and_then(|data| self.write_deck_data(name, &data));
+            if let Err(e) = migrated {
+                log::error!("Failed to migrate deck '{}': {}", name, e);
             }
-            Err(_) => None,
         }
+        Ok(cards)
     }
 
     /// Save a deck (cards + index entry).
-    pub fn save_deck(&self, name: &str, cards: &[Card]) {
-        let key_name = format!("deck.{}", name);
-        let data = serialize_cards(cards);
-
-        // Write card data
-        match self.pddb.get(DICT_NAME, &key_name, None, true, true, Some(data.len()), None::<fn()>) {
-            Ok(mut key) => {
-                key.seek(SeekFrom::Start(0)).ok();
-                if key.write_all(&data).is_ok() {
-                    self.pddb.sync().ok();
-                }
-            }
-            Err(e) => log::error!("Failed to save deck '{}': {:?}", name, e),
-        }
+    /// Nothing is written if the name is invalid or the cards can't be
+    /// serialized.
+    pub fn save_deck(&self, name: &str, cards: &[Card]) -> Result<(), Error> {
+        check_deck_name(name)?;
+        let data = serialize_cards(cards)?;
+        self.write_deck_data(name, &data)?;
 
         // Update index
         let mut names = self.read_index();
         if !names.iter().any(|n| n == name) {
             names.push(name.to_string());
-            self.write_index(&names);
+            self.write_index(&names)?;
         }
+        Ok(())
     }
 
-    /// Delete a deck (cards + index entry).
-    pub fn delete_deck(&self, name: &str) {
-        let key_name = format!("deck.{}", name);
-        self.pddb.delete_key(DICT_NAME, &key_name, None).ok();
+    /// Merge imported cards into an existing deck and save it. Review state is
+    /// kept for unchanged cards, reset for cards whose text changed, and
+    /// dropped for removed cards.
+    pub fn merge_deck(&self, name: &str, incoming: Vec<Card>, remove_missing: bool) -> Result<MergeSummary, Error> {
+        check_deck_name(name)?;
+        let existing = self.load_deck(name)?;
+        let merged = merge_cards(&existing, incoming, remove_missing);
+        let mut states = self.load_states(name, &merged.cards);
+        for (card, state) in merged.cards.iter().zip(states.iter_mut()) {
+            if merged.updated_ids.contains(&card.id) {
+                *state = CardState::new();
+            }
+        }
+        self.save_deck(name, &merged.cards)?;
+        self.save_states(name, &merged.cards, &states)?;
+        Ok(merged.summary)
+    }
+
+    /// Load review state for a deck, one entry per card, matched by card id.
+    /// Cards without stored state (new or never reviewed) get a fresh state.
+    pub fn load_states(&self, name: &str, cards: &[Card]) -> Vec<CardState> {
+        let ids: Vec<u64> = cards.iter().map(|c| c.id).collect();
+        self.read_key(&format!("sched.{}", name))
+            .ok()
+            .and_then(|data| deserialize_states(&data, &ids))
+            .unwrap_or_else(|| vec![CardState::new(); cards.len()])
+    }
+
+    /// Save review state for a deck; `states` runs parallel to `cards`.
+    pub fn save_states(&self, name: &str, cards: &[Card], states: &[CardState]) -> Result<(), Error> {
+        let ids: Vec<u64> = cards.iter().map(|c| c.id).collect();
+        self.write_key(&format!("sched.{}", name), &serialize_states(&ids, states))
+    }
+
+    /// Load a deck's scheduling settings, or the defaults if none are stored.
+    pub fn load_settings(&self, name: &str) -> DeckSettings {
+        self.read_key(&format!("settings.{}", name))
+            .ok()
+            .and_then(|data| deserialize_settings(&data))
+            .unwrap_or_default()
+    }
+
+    /// Save a deck's scheduling settings.
+    pub fn save_settings(&self, name: &str, settings: &DeckSettings) -> Result<(), Error> {
+        self.write_key(&format!("settings.{}", name), &serialize_settings(settings))
+    }
+
+    /// Load export preferences, or the defaults if none are stored.
+    pub fn load_export_settings(&self) -> ExportSettings {
+        self.read_key(EXPORT_SETTINGS_KEY)
+            .ok()
+            .and_then(|data| deserialize_export_settings(&data))
+            .unwrap_or_default()
+    }
+
+    pub fn save_export_settings(&self, settings: &ExportSettings) -> Result<(), Error> {
+        self.write_key(EXPORT_SETTINGS_KEY, &serialize_export_settings(settings))
+    }
+
+    /// Append one answer to a deck's review log.
+    pub fn append_review(&self, name: &str, entry: &ReviewEntry) -> Result<(), Error> {
+        let key_name = format!("log.{}", name);
+        let mut key = self
+            .pddb
+            .get(DICT_NAME, &key_name, None, true, true, None, None::<fn()>)
+            .map_err(Error::Pddb)?;
+        if key.seek(SeekFrom::End(0)).map_err(Error::Pddb)? == 0 {
+            key.write_all(&log_header()).map_err(Error::Pddb)?;
+        }
+        key.write_all(&serialize_entry(entry)).map_err(Error::Pddb)?;
+        self.pddb.sync().map_err(Error::Pddb)
+    }
+
+    /// Load a deck's full review log, oldest entry first.
+    pub fn load_review_log(&self, name: &str) -> Vec<ReviewEntry> {
+        self.read_key(&format!("log.{}", name))
+            .ok()
+            .and_then(|data| deserialize_log(&data))
+            .unwrap_or_default()
+    }
+
+    /// Delete a deck (cards + review state + settings + review log + index entry).
+    pub fn delete_deck(&self, name: &str) -> Result<(), Error> {
+        self.pddb.delete_key(DICT_NAME, &format!("deck.{}", name), None).map_err(Error::Pddb)?;
+        // Side keys may not exist if the deck was never reviewed
+        for prefix in ["sched", "settings", "log"] {
+            self.pddb.delete_key(DICT_NAME, &format!("{}.{}", prefix, name), None).ok();
+        }
 
         let mut names = self.read_index();
         names.retain(|n| n != name);
-        self.write_index(&names);
-        self.pddb.sync().ok();
+        self.write_index(&names)
+    }
+
+    /// Gather the whole library: the index, export settings, and every
+    /// deck's cards, review state, settings and review log, as stored.
+    pub fn backup(&self) -> Result<Backup, Error> {
+        let mut entries = Vec::new();
+        for key in [INDEX_KEY, EXPORT_SETTINGS_KEY] {
+            if let Ok(data) = self.read_key(key) {
+                entries.push((key.to_string(), data));
+            }
+        }
+        for name in self.read_index() {
+            for prefix in DECK_KEY_PREFIXES {
+                let key = format!("{}.{}", prefix, name);
+                match self.read_key(&key) {
+                    Ok(data) => entries.push((key, data)),
+                    // Side keys don't exist until a deck is reviewed or configured
+                    Err(_) if prefix != "deck" => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(Backup { created: unix_time(), entries })
+    }
+
+    /// Replace the library with a backup that has passed `Backup::verify`.
+    /// Keys are rewritten before the index, so the index never names a deck
+    /// that isn't there; decks missing from the backup are deleted last.
+    /// Nothing is written if any deck name is invalid.
+    pub fn restore(&self, backup: &Backup) -> Result<(), Error> {
+        let old_names = self.read_index();
+        let new_names = backup.deck_names();
+        new_names.iter().try_for_each(|name| check_deck_name(name))?;
+        for (key, data) in &backup.entries {
+            if key != INDEX_KEY {
+                self.replace_key(key, data)?;
+            }
+        }
+        for name in &new_names {
+            // A side key the backup doesn't have would outlive the restore
+            for prefix in &DECK_KEY_PREFIXES[1..] {
+                let key = format!("{}.{}", prefix, name);
+                if backup.get(&key).is_none() {
+                    self.pddb.delete_key(DICT_NAME, &key, None).ok();
+                }
+            }
+        }
+        self.replace_key(INDEX_KEY, backup.get(INDEX_KEY).unwrap_or_default())?;
+        for name in old_names.iter().filter(|n| !new_names.contains(n)) {
+            for prefix in DECK_KEY_PREFIXES {
+                self.pddb.delete_key(DICT_NAME, &format!("{}.{}", prefix, name), None).ok();
+            }
+        }
+        self.pddb.sync().map_err(Error::Pddb)
     }
 
     /// Check if the index exists (for first-run detection).
@@ -89,81 +246,97 @@ impl DeckStorage {
         if self.has_index() {
             return;
         }
-        let demo_cards = vec![
-            Card {
-                front: "What is Xous?".to_string(),
-                back: "A microkernel OS for the Precursor, using message-passing IPC between servers.".to_string(),
-            },
-            Card {
-                front: "What is the Precursor display?".to_string(),
-                back: "336x536 pixels, 1-bit (black and white only). No grayscale or color.".to_string(),
-            },
-            Card {
-                front: "What is the PDDB?".to_string(),
-                back: "Plausibly Deniable Database. Encrypted key-value storage organized as basis > dictionary > key.".to_string(),
-            },
-            Card {
-                front: "How do apps draw to screen?".to_string(),
-                back: "Through the GAM (Graphics Abstraction Manager) service, which manages canvases and trust levels.".to_string(),
-            },
-            Card {
-                front: "What CPU does Precursor use?".to_string(),
-                back: "100MHz VexRISC-V RV32IMAC. Single core, no FPU.".to_string(),
-            },
-            Card {
-                front: "How does IPC work in Xous?".to_string(),
-                back: "Message passing. Scalar messages (4 usizes) or memory messages (buffer transfer). No shared memory.".to_string(),
-            },
-            Card {
-                front: "What is a Server ID (SID)?".to_string(),
-                back: "A unique address for a process's message queue. Obtained by registering a name with xous-names.".to_string(),
-            },
-            Card {
-                front: "How do apps handle input?".to_string(),
-                back: "Register rawkeys_id with GAM. Keys arrive as up to 4 chars packed in scalar message parameters.".to_string(),
-            },
+        let mut demo_cards = vec![
+            Card::new(
+                "What is Xous?",
+                "A microkernel OS for the Precursor, using message-passing IPC between servers.",
+            ),
+            Card::new(
+                "What is the Precursor display?",
+                "336x536 pixels, 1-bit (black and white only). No grayscale or color.",
+            ),
+            Card::new(
+                "What is the PDDB?",
+                "Plausibly Deniable Database. Encrypted key-value storage organized as basis > dictionary > key.",
+            ),
+            Card::new(
+                "How do apps draw to screen?",
+                "Through the GAM (Graphics Abstraction Manager) service, which manages canvases and trust levels.",
+            ),
+            Card::new(
+                "What CPU does Precursor use?",
+                "100MHz VexRISC-V RV32IMAC. Single core, no FPU.",
+            ),
+            Card::new(
+                "How does IPC work in Xous?",
+                "Message passing. Scalar messages (4 usizes) or memory messages (buffer transfer). No shared memory.",
+            ),
+            Card::new(
+                "What is a Server ID (SID)?",
+                "A unique address for a process's message queue. Obtained by registering a name with xous-names.",
+            ),
+            Card::new(
+                "How do apps handle input?",
+                "Register rawkeys_id with GAM. Keys arrive as up to 4 chars packed in scalar message parameters.",
+            ),
         ];
-        self.save_deck("Xous Basics", &demo_cards);
+        assign_ids(&mut demo_cards);
+        if let Err(e) = self.save_deck("Xous Basics", &demo_cards) {
+            log::error!("Failed to save demo deck: {}", e);
+        }
     }
 
     fn read_index(&self) -> Vec<String> {
-        match self.pddb.get(DICT_NAME, INDEX_KEY, None, false, false, None, None::<fn()>) {
-            Ok(mut key) => {
-                let mut data = String::new();
-                key.seek(SeekFrom::Start(0)).ok();
-                if key.read_to_string(&mut data).is_ok() {
-                    data.lines()
-                        .filter(|l| !l.is_empty())
-                        .map(|l| l.to_string())
-                        .collect()
-                } else {
-                    Vec::new()
-                }
-            }
+        match self.read_key(INDEX_KEY) {
+            Ok(data) => parse_index(&data),
             Err(_) => Vec::new(),
         }
     }
 
-    fn write_index(&self, names: &[String]) {
-        let data = names.join("\n");
-        match self.pddb.get(DICT_NAME, INDEX_KEY, None, true, true, Some(data.len()), None::<fn()>) {
-            Ok(mut key) => {
-                key.seek(SeekFrom::Start(0)).ok();
-                key.write_all(data.as_bytes()).ok();
-                self.pddb.sync().ok();
-            }
-            Err(e) => log::error!("Failed to write index: {:?}", e),
-        }
+    fn write_index(&self, names: &[String]) -> Result<(), Error> {
+        self.write_key(INDEX_KEY, names.join("\n").as_bytes())
+    }
+
+    fn write_deck_data(&self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.write_key(&format!("deck.{}", name), data)
+    }
+
+    fn read_key(&self, key_name: &str) -> Result<Vec<u8>, Error> {
+        let mut key = self
+            .pddb
+            .get(DICT_NAME, key_name, None, false, false, None, None::<fn()>)
+            .map_err(Error::Pddb)?;
+        let mut data = Vec::new();
+        key.seek(SeekFrom::Start(0)).map_err(Error::Pddb)?;
+        key.read_to_end(&mut data).map_err(Error::Pddb)?;
+        Ok(data)
+    }
+
+    fn write_key(&self, key_name: &str, data: &[u8]) -> Result<(), Error> {
+        let mut key = self
+            .pddb
+            .get(DICT_NAME, key_name, None, true, true, Some(data.len()), None::<fn()>)
+            .map_err(Error::Pddb)?;
+        key.seek(SeekFrom::Start(0)).map_err(Error::Pddb)?;
+        key.write_all(data).map_err(Error::Pddb)?;
+        self.pddb.sync().map_err(Error::Pddb)
+    }
+
+    /// Write a key from scratch, so no bytes of a longer old value remain.
+    fn replace_key(&self, key_name: &str, data: &[u8]) -> Result<(), Error> {
+        self.pddb.delete_key(DICT_NAME, key_name, None).ok();
+        self.write_key(key_name, data)
     }
 
     fn get_card_count(&self, name: &str) -> u32 {
         let key_name = format!("deck.{}", name);
         match self.pddb.get(DICT_NAME, &key_name, None, false, false, None, None::<fn()>) {
             Ok(mut key) => {
-                let mut buf = [0u8; 4];
+                // Enough for the header and count of any format
+                let mut buf = Vec::with_capacity(HEADER_LEN + 4);
                 key.seek(SeekFrom::Start(0)).ok();
-                if key.read_exact(&mut buf).is_ok() {
-                    u32::from_le_bytes(buf)
+                if (&mut key).take((HEADER_LEN + 4) as u64).read_to_end(&mut buf).is_ok() {
+                    read_card_count(&buf).unwrap_or(0)
                 } else {
                     0
                 }
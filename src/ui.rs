@@ -1,9 +1,14 @@
 use std::fmt::Write;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 use gam::{Gam, GlyphStyle, Gid};
 use gam::menu::*;
 
+use crate::backup::BackupSummary;
 use crate::deck::{Card, DeckMeta};
+use crate::import::{BundleEntry, ExportProgress};
+use crate::merge::MergeSummary;
 
 pub fn clear_screen(gam: &Gam, content: Gid, screensize: Point) {
     gam.draw_rectangle(
@@ -171,20 +176,76 @@ pub fn draw_card_review(
     );
     nav_tv.style = GlyphStyle::Small;
     nav_tv.clear_area = true;
-    write!(
-        nav_tv.text,
-        "{} {}/{}  F2=flip F3=next F4=back",
-        deck_name,
-        card_index + 1,
-        total_cards
-    )
-    .unwrap();
+    if showing_back {
+        write!(
+            nav_tv.text,
+            "{} {}/{}\n1=again 2=hard 3=good 4=easy",
+            deck_name,
+            card_index + 1,
+            total_cards
+        )
+        .unwrap();
+    } else {
+        write!(
+            nav_tv.text,
+            "{} {}/{}  F2=flip F3=next F4=back",
+            deck_name,
+            card_index + 1,
+            total_cards
+        )
+        .unwrap();
+    }
     gam.post_textview(&mut nav_tv).expect("can't post nav");
 
     gam.redraw().expect("can't redraw");
 }
 
-pub fn draw_import_wait(gam: &Gam, content: Gid, screensize: Point, port: u16) {
+pub fn draw_review_done(
+    gam: &Gam,
+    content: Gid,
+    screensize: Point,
+    deck_name: &str,
+    reviewed: usize,
+    total_cards: usize,
+) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "{}", deck_name).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 160)),
+    );
+    tv.style = GlyphStyle::Regular;
+    tv.clear_area = true;
+    if reviewed == 0 {
+        write!(tv.text, "No cards due.\n\n{} cards in deck.", total_cards).unwrap();
+    } else {
+        write!(tv.text, "Session complete.\n\nReviewed {} cards.", reviewed).unwrap();
+    }
+    gam.post_textview(&mut tv).expect("can't post status");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "a=review all  q=back").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+/// Waiting for an import, with the one-time pairing code the sender must present.
+pub fn draw_import_wait(gam: &Gam, content: Gid, screensize: Point, port: u16, code: &str) {
     clear_screen(gam, content, screensize);
 
     let mut title_tv = TextView::new(
@@ -198,25 +259,26 @@ pub fn draw_import_wait(gam: &Gam, content: Gid, screensize: Point, port: u16) {
 
     let mut tv1 = TextView::new(
         content,
-        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 90)),
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 110)),
     );
     tv1.style = GlyphStyle::Regular;
     tv1.clear_area = true;
-    write!(tv1.text, "Listening on port {}...", port).unwrap();
+    write!(tv1.text, "Listening on port {}...\nPairing code: {}", port, code).unwrap();
     gam.post_textview(&mut tv1).expect("can't post status");
 
     let mut tv2 = TextView::new(
         content,
-        TextBounds::BoundingBox(Rectangle::new_coords(12, 100, screensize.x - 12, 180)),
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 120, screensize.x - 12, 200)),
     );
     tv2.style = GlyphStyle::Small;
     tv2.clear_area = true;
-    write!(tv2.text, "From your computer run:\n  cat deck.tsv | nc <device-ip> {}", port).unwrap();
+    write!(tv2.text, "From your computer run:\n  (echo '#code:{}'; cat deck.tsv) | nc <device-ip> {}", code, port)
+        .unwrap();
     gam.post_textview(&mut tv2).expect("can't post instructions");
 
     let mut tv3 = TextView::new(
         content,
-        TextBounds::BoundingBox(Rectangle::new_coords(12, 200, screensize.x - 12, 280)),
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 210, screensize.x - 12, 290)),
     );
     tv3.style = GlyphStyle::Small;
     tv3.clear_area = true;
@@ -235,6 +297,356 @@ pub fn draw_import_wait(gam: &Gam, content: Gid, screensize: Point, port: u16) {
     gam.redraw().expect("can't redraw");
 }
 
+pub fn draw_restore_wait(gam: &Gam, content: Gid, screensize: Point, port: u16, code: &str) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "Restore Backup").unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv1 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 110)),
+    );
+    tv1.style = GlyphStyle::Regular;
+    tv1.clear_area = true;
+    write!(tv1.text, "Listening on port {}...\nPairing code: {}", port, code).unwrap();
+    gam.post_textview(&mut tv1).expect("can't post status");
+
+    let mut tv2 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 120, screensize.x - 12, 220)),
+    );
+    tv2.style = GlyphStyle::Small;
+    tv2.clear_area = true;
+    write!(
+        tv2.text,
+        "From your computer run:\n  (echo '#code:{}'; cat flashcards.fcbk) | nc <device-ip> {}\n\n\
+         The backup is checked and shown before anything is replaced.",
+        code, port
+    )
+    .unwrap();
+    gam.post_textview(&mut tv2).expect("can't post instructions");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "Waiting for connection... (q=cancel)").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+/// A verified backup awaiting confirmation, with what it holds and how many
+/// decks it replaces.
+pub fn draw_restore_confirm(gam: &Gam, content: Gid, screensize: Point, summary: &BackupSummary, current: usize) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "Restore Backup?").unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv1 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 150)),
+    );
+    tv1.style = GlyphStyle::Regular;
+    tv1.clear_area = true;
+    write!(
+        tv1.text,
+        "Backup verified\n{} decks, {} cards\n{} reviews logged",
+        summary.decks, summary.cards, summary.reviews
+    )
+    .unwrap();
+    gam.post_textview(&mut tv1).expect("can't post summary");
+
+    let mut tv2 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 160, screensize.x - 12, 260)),
+    );
+    tv2.style = GlyphStyle::Small;
+    tv2.clear_area = true;
+    write!(
+        tv2.text,
+        "This replaces all {} decks on the device, with their settings and review history.",
+        current
+    )
+    .unwrap();
+    gam.post_textview(&mut tv2).expect("can't post warning");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "y=restore  n=cancel").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+/// Passphrase entry. Only the number of characters typed is shown.
+pub fn draw_passphrase(
+    gam: &Gam,
+    content: Gid,
+    screensize: Point,
+    title: &str,
+    prompt: &str,
+    typed: usize,
+    note: &str,
+) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "{}", title).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut entry_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 120)),
+    );
+    entry_tv.style = GlyphStyle::Regular;
+    entry_tv.clear_area = true;
+    write!(entry_tv.text, "{}\n{}_", prompt, "*".repeat(typed)).unwrap();
+    gam.post_textview(&mut entry_tv).expect("can't post entry");
+
+    let mut note_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 130, screensize.x - 12, 230)),
+    );
+    note_tv.style = GlyphStyle::Small;
+    note_tv.clear_area = true;
+    write!(note_tv.text, "{}", note).unwrap();
+    gam.post_textview(&mut note_tv).expect("can't post note");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "Enter=ok  F4=cancel").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+/// Parsed import awaiting confirmation: name, the first few cards and `details`
+/// (counts and name-collision choices).
+pub fn draw_import_preview(
+    gam: &Gam,
+    content: Gid,
+    screensize: Point,
+    deck_name: &str,
+    first_cards: &[Card],
+    details: &str,
+) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "Import: {}", deck_name).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut cards_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 56, screensize.x - 12, 250)),
+    );
+    cards_tv.style = GlyphStyle::Small;
+    cards_tv.clear_area = true;
+    for card in first_cards {
+        write!(cards_tv.text, "{}\n  -> {}\n", card.front, card.back).unwrap();
+    }
+    gam.post_textview(&mut cards_tv).expect("can't post cards");
+
+    let mut details_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 260, screensize.x - 12, screensize.y - 50)),
+    );
+    details_tv.style = GlyphStyle::Regular;
+    details_tv.clear_area = true;
+    write!(details_tv.text, "{}", details).unwrap();
+    gam.post_textview(&mut details_tv).expect("can't post details");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "a=accept r=rename m=merge d=discard").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+pub fn draw_import_summary(gam: &Gam, content: Gid, screensize: Point, deck_name: &str, summary: &MergeSummary) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "Merged into {}", deck_name).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 200)),
+    );
+    tv.style = GlyphStyle::Regular;
+    tv.clear_area = true;
+    write!(
+        tv.text,
+        "Added:     {}\nUpdated:   {}\nUnchanged: {}\nRemoved:   {}",
+        summary.added, summary.updated, summary.unchanged, summary.removed
+    )
+    .unwrap();
+    if summary.over_limit > 0 {
+        write!(tv.text, "\n\n{} new cards not added:\ndeck is full", summary.over_limit).unwrap();
+    }
+    gam.post_textview(&mut tv).expect("can't post summary");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "Any key to continue").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+/// Lines of the bundle summary that fit between its title and footer.
+pub fn bundle_summary_lines(screensize: Point) -> usize {
+    ((screensize.y - 110) / 20).max(1) as usize
+}
+
+/// Per-deck results of a multi-deck import, scrolled to start at `scroll`.
+pub fn draw_bundle_summary(gam: &Gam, content: Gid, screensize: Point, entries: &[BundleEntry], scroll: usize) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "Imported {} decks", entries.len()).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, screensize.y - 50)),
+    );
+    tv.style = GlyphStyle::Small;
+    tv.clear_area = true;
+    for entry in entries.iter().skip(scroll).take(bundle_summary_lines(screensize)) {
+        writeln!(tv.text, "{}", entry).unwrap();
+    }
+    gam.post_textview(&mut tv).expect("can't post summary");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "j/k scroll, any other key to continue").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
+pub fn draw_export_wait(
+    gam: &Gam,
+    content: Gid,
+    screensize: Point,
+    title: &str,
+    port: u16,
+    receive_command: &str,
+    total_bytes: usize,
+    progress: ExportProgress,
+) {
+    clear_screen(gam, content, screensize);
+
+    let mut title_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 20, screensize.x - 12, 48)),
+    );
+    title_tv.style = GlyphStyle::Bold;
+    title_tv.clear_area = true;
+    write!(title_tv.text, "{}", title).unwrap();
+    gam.post_textview(&mut title_tv).expect("can't post title");
+
+    let mut tv1 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 120)),
+    );
+    tv1.style = GlyphStyle::Regular;
+    tv1.clear_area = true;
+    match progress {
+        ExportProgress::Encrypting => {
+            write!(tv1.text, "Encrypting...\n{} bytes", total_bytes).unwrap();
+        }
+        ExportProgress::Waiting { remaining_secs } => {
+            write!(tv1.text, "Listening on port {}...\n{} bytes ready, {}s left", port, total_bytes, remaining_secs)
+                .unwrap();
+        }
+        ExportProgress::Sending { sent } => {
+            write!(tv1.text, "Sending...\n{} / {} bytes", sent, total_bytes).unwrap();
+        }
+    }
+    gam.post_textview(&mut tv1).expect("can't post status");
+
+    let mut tv2 = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 130, screensize.x - 12, 210)),
+    );
+    tv2.style = GlyphStyle::Small;
+    tv2.clear_area = true;
+    write!(tv2.text, "From your computer run:\n  {}", receive_command).unwrap();
+    gam.post_textview(&mut tv2).expect("can't post instructions");
+
+    let mut nav_tv = TextView::new(
+        content,
+        TextBounds::BoundingBox(Rectangle::new_coords(12, screensize.y - 40, screensize.x - 12, screensize.y - 10)),
+    );
+    nav_tv.style = GlyphStyle::Small;
+    nav_tv.clear_area = true;
+    write!(nav_tv.text, "Waiting for connection... (q=cancel)").unwrap();
+    gam.post_textview(&mut nav_tv).expect("can't post footer");
+
+    gam.redraw().expect("can't redraw");
+}
+
 pub fn draw_menu(
     gam: &Gam,
     content: Gid,
@@ -337,6 +749,7 @@ pub fn draw_deck_menu(
     screensize: Point,
     deck_name: &str,
     card_count: u32,
+    details: &str,
     confirm_delete: bool,
 ) {
     clear_screen(gam, content, screensize);
@@ -352,17 +765,17 @@ pub fn draw_deck_menu(
 
     let mut info_tv = TextView::new(
         content,
-        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 120)),
+        TextBounds::BoundingBox(Rectangle::new_coords(12, 60, screensize.x - 12, 150)),
     );
     info_tv.style = GlyphStyle::Regular;
     info_tv.clear_area = true;
-    write!(info_tv.text, "Name: {}\nCards: {}", deck_name, card_count).unwrap();
+    write!(info_tv.text, "Name: {}\nCards: {}\n{}", deck_name, card_count, details).unwrap();
     gam.post_textview(&mut info_tv).expect("can't post info");
 
     if confirm_delete {
         let mut confirm_tv = TextView::new(
             content,
-            TextBounds::BoundingBox(Rectangle::new_coords(12, 140, screensize.x - 12, 220)),
+            TextBounds::BoundingBox(Rectangle::new_coords(12, 160, screensize.x - 12, 240)),
         );
         confirm_tv.style = GlyphStyle::Regular;
         confirm_tv.clear_area = true;
@@ -379,9 +792,124 @@ pub fn draw_deck_menu(
     if confirm_delete {
         write!(nav_tv.text, "y=delete  n=cancel").unwrap();
     } else {
-        write!(nav_tv.text, "d=delete  F4=back").unwrap();
+        write!(nav_tv.text, "e/f/t/c=export s/b=sched d=del q/F4=back").unwrap();
     }
     gam.post_textview(&mut nav_tv).expect("can't post footer");
 
     gam.redraw().expect("can't redraw");
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// How long a message stays up before it is dismissed.
+    fn timeout(self) -> Duration {
+        Duration::from_millis(match self {
+            Severity::Info => 3000,
+            Severity::Warning => 5000,
+            Severity::Error => 8000,
+        })
+    }
+}
+
+struct Status {
+    text: String,
+    severity: Severity,
+}
+
+/// Transient message bar drawn over the bottom of whatever screen is showing.
+/// One timer thread, started with the bar, is re-armed by each post and
+/// sends `expire_op` back to the app with the post's generation when it
+/// runs out, so a newer message is never dismissed by an older message's
+/// timer.
+pub struct StatusBar {
+    current: Option<Status>,
+    generation: usize,
+    timer: Sender<(usize, Instant)>,
+}
+
+impl StatusBar {
+    pub fn new(cid: xous::CID, expire_op: usize) -> Self {
+        let (timer, arm) = mpsc::channel::<(usize, Instant)>();
+        std::thread::spawn(move || {
+            let mut armed: Option<(usize, Instant)> = None;
+            loop {
+                let next = match armed {
+                    Some((_, deadline)) => arm.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                    None => arm.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match next {
+                    Ok(rearmed) => armed = Some(rearmed),
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some((generation, _)) = armed.take() {
+                            xous::send_message(cid, xous::Message::new_scalar(expire_op, generation, 0, 0, 0)).ok();
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        Self { current: None, generation: 0, timer }
+    }
+
+    /// Show a message, replacing any current one.
+    pub fn post(&mut self, severity: Severity, text: String) {
+        self.generation = self.generation.wrapping_add(1);
+        self.current = Some(Status { text, severity });
+        self.timer.send((self.generation, Instant::now() + severity.timeout())).ok();
+    }
+
+    /// Dismiss the message posted as `generation`, if it is still showing.
+    /// Returns true if the screen needs a redraw.
+    pub fn expire(&mut self, generation: usize) -> bool {
+        if self.current.is_some() && generation == self.generation {
+            self.current = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn draw(&self, gam: &Gam, content: Gid, screensize: Point) {
+        let status = match &self.current {
+            Some(status) => status,
+            None => return,
+        };
+        let top = screensize.y - 64;
+        let dark = status.severity != Severity::Info;
+        gam.draw_rectangle(
+            content,
+            Rectangle::new_with_style(
+                Point::new(0, top),
+                Point::new(screensize.x, screensize.y),
+                DrawStyle {
+                    fill_color: Some(if dark { PixelColor::Dark } else { PixelColor::Light }),
+                    stroke_color: Some(PixelColor::Dark),
+                    stroke_width: 2,
+                },
+            ),
+        )
+        .expect("can't draw status bar");
+
+        let mut tv = TextView::new(
+            content,
+            TextBounds::BoundingBox(Rectangle::new_coords(12, top + 6, screensize.x - 12, screensize.y - 6)),
+        );
+        tv.style = GlyphStyle::Small;
+        tv.invert = dark;
+        let prefix = match status.severity {
+            Severity::Info => "",
+            Severity::Warning => "! ",
+            Severity::Error => "ERROR ",
+        };
+        write!(tv.text, "{}{}", prefix, status.text).unwrap();
+        gam.post_textview(&mut tv).expect("can't post status");
+
+        gam.redraw().expect("can't redraw");
+    }
+}
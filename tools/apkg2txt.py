@@ -0,0 +1,157 @@
+#!/usr/bin/env python3
+"""Convert an Anki .apkg package into plain-text decks for the Precursor.
+
+An .apkg is a zip holding the collection as a SQLite database. This reads
+the notes, their fields, tags and deck names, and writes one Anki
+"Notes in Plain Text" file per deck, which the device's import port
+understands. With --send, the decks go straight to the device as one
+bundle, each starting with its own #name: line.
+
+Scheduling data is not carried over; cards start fresh on the device.
+
+Usage:
+    apkg2txt.py deck.apkg                 # write <deck name>.txt files
+    apkg2txt.py deck.apkg --deck Spanish  # only that deck (and subdecks)
+    apkg2txt.py deck.apkg --send 10.0.0.5 # push all decks to port 7878
+
+The push needs the pairing code the device shows while it waits for an
+import. The device keeps the first 32 decks of a bundle; narrow a larger
+package with --deck.
+
+Needs only the Python 3 standard library. Packages exported by Anki 2.1.50+
+without "Support older Anki versions" are zstd-compressed; those also need
+the `zstandard` module (pip install zstandard).
+"""
+
+import argparse
+import io
+import json
+import os
+import socket
+import sqlite3
+import sys
+import tempfile
+import zipfile
+
+PORT = 7878
+FIELD_SEPARATOR = "\x1f"
+
+
+def read_collection(apkg_path):
+    """Extract the collection database to a temporary file and open it."""
+    with zipfile.ZipFile(apkg_path) as z:
+        names = z.namelist()
+        if "collection.anki21b" in names:
+            data = decompress_zstd(z.read("collection.anki21b"))
+        elif "collection.anki21" in names:
+            data = z.read("collection.anki21")
+        elif "collection.anki2" in names:
+            data = z.read("collection.anki2")
+        else:
+            sys.exit("%s: no Anki collection inside" % apkg_path)
+    tmp = tempfile.NamedTemporaryFile(suffix=".anki2", delete=False)
+    tmp.write(data)
+    tmp.close()
+    return sqlite3.connect(tmp.name), tmp.name
+
+
+def decompress_zstd(data):
+    try:
+        import zstandard
+    except ImportError:
+        sys.exit("This package is zstd-compressed. Install the zstandard module, "
+                 "or re-export from Anki with \"Support older Anki versions\" ticked.")
+    return zstandard.ZstdDecompressor().stream_reader(io.BytesIO(data)).read()
+
+
+def deck_names(db):
+    """Map deck id to its full name, with subdecks joined by '::'."""
+    tables = {row[0] for row in db.execute("SELECT name FROM sqlite_master WHERE type='table'")}
+    if "decks" in tables:
+        return {did: name.replace(FIELD_SEPARATOR, "::") for did, name in db.execute("SELECT id, name FROM decks")}
+    (decks_json,) = db.execute("SELECT decks FROM col").fetchone()
+    return {int(did): deck["name"] for did, deck in json.loads(decks_json).items()}
+
+
+def read_notes(db):
+    """Yield (deck name, guid, fields, tags) for each note, placed in the
+    deck of its first card."""
+    names = deck_names(db)
+    rows = db.execute(
+        "SELECT n.guid, n.flds, n.tags, "
+        "(SELECT c.did FROM cards c WHERE c.nid = n.id ORDER BY c.ord LIMIT 1) "
+        "FROM notes n ORDER BY n.id"
+    )
+    for guid, flds, tags, did in rows:
+        if did is None:
+            continue
+        yield names.get(did, "Default"), guid, flds.split(FIELD_SEPARATOR), tags.split()
+
+
+def quote(field):
+    """Quote a field the way Anki's own export does."""
+    if any(c in field for c in '\t"\n\r'):
+        return '"' + field.replace('"', '""') + '"'
+    return field
+
+
+def deck_text(name, notes):
+    """One deck in Anki's plain-text format. The leading #name: line lets
+    the device split a bundle of several decks sent together."""
+    out = ["#name:" + name, "#separator:tab", "#html:true", "#deck:" + name, "#guid column:1", "#tags column:4"]
+    for guid, fields, tags in notes:
+        front = fields[0] if fields else ""
+        back = fields[1] if len(fields) > 1 else ""
+        out.append("\t".join(quote(f) for f in (guid, front, back, " ".join(tags))))
+    return "\n".join(out) + "\n"
+
+
+def send(host, code, text):
+    with socket.create_connection((host, PORT)) as s:
+        s.sendall(("#code:%s\n" % code).encode("ascii"))
+        s.sendall(text.encode("utf-8"))
+        s.shutdown(socket.SHUT_WR)
+        while True:
+            reply = s.recv(4096)
+            if not reply:
+                break
+            sys.stdout.write(reply.decode("utf-8", "replace"))
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Convert an Anki .apkg into Precursor flashcard decks.")
+    parser.add_argument("apkg")
+    parser.add_argument("--deck", help="only convert this deck and its subdecks")
+    parser.add_argument("--send", metavar="HOST", help="push the decks to the device instead of writing files")
+    parser.add_argument("--out", default=".", help="directory for the .txt files")
+    args = parser.parse_args()
+
+    db, db_path = read_collection(args.apkg)
+    try:
+        decks = {}
+        for name, guid, fields, tags in read_notes(db):
+            if args.deck and name != args.deck and not name.startswith(args.deck + "::"):
+                continue
+            decks.setdefault(name, []).append((guid, fields, tags))
+    finally:
+        db.close()
+        os.unlink(db_path)
+
+    if not decks:
+        sys.exit("No notes found")
+    decks = sorted(decks.items())
+    if args.send:
+        code = input("Press i on the device, then enter the pairing code: ").strip()
+        total = sum(len(notes) for _, notes in decks)
+        print("Sending %d decks (%d notes)" % (len(decks), total))
+        send(args.send, code, "".join(deck_text(name, notes) for name, notes in decks))
+        return
+    for name, notes in decks:
+        path = os.path.join(args.out, name.replace("::", "_").replace("/", "_") + ".txt")
+        with open(path, "w", encoding="utf-8") as f:
+            f.write(deck_text(name, notes))
+        print("Wrote %s (%d notes)" % (path, len(notes)))
+
+
+if __name__ == "__main__":
+    main()
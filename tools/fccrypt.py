@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Encrypt and decrypt Precursor flashcard export files.
+
+With "Encrypt exports" on, the device sends decks sealed with a passphrase.
+This reads those files on the computer, and seals edited decks so they can
+be sent back encrypted; the device asks for the passphrase on import.
+
+The format matches src/crypto.rs: PBKDF2-HMAC-SHA256 derives the key,
+ChaCha20-Poly1305 seals the payload, and the header is authenticated too.
+
+    [4: magic "FCEN"][u8: version][u8: kdf][u16: reserved][u32: iterations LE]
+    [16: salt][12: nonce][ciphertext][16: tag]
+
+Usage:
+    fccrypt.py decrypt deck.tsv.enc            # writes deck.tsv
+    fccrypt.py encrypt deck.tsv                # writes deck.tsv.enc
+    fccrypt.py decrypt deck.tsv.enc -o -       # print to stdout
+
+Needs the `cryptography` module (pip install cryptography).
+"""
+
+import argparse
+import getpass
+import hashlib
+import os
+import struct
+import sys
+
+MAGIC = b"FCEN"
+VERSION = 1
+KDF_PBKDF2_SHA256 = 1
+SALT_LEN = 16
+NONCE_LEN = 12
+HEADER_LEN = 12 + SALT_LEN + NONCE_LEN
+DEFAULT_ITERATIONS = 100_000
+# The device refuses files asking for more rounds than this
+MAX_ITERATIONS = 2_000_000
+
+
+def chacha(key):
+    try:
+        from cryptography.hazmat.primitives.ciphers.aead import ChaCha20Poly1305
+    except ImportError:
+        sys.exit("This needs the cryptography module: pip install cryptography")
+    return ChaCha20Poly1305(key)
+
+
+def derive_key(passphrase, salt, iterations):
+    return hashlib.pbkdf2_hmac("sha256", passphrase.encode("utf-8"), salt, iterations, 32)
+
+
+def seal(plain, passphrase, iterations=DEFAULT_ITERATIONS, salt=None, nonce=None):
+    salt = salt if salt is not None else os.urandom(SALT_LEN)
+    nonce = nonce if nonce is not None else os.urandom(NONCE_LEN)
+    header = MAGIC + struct.pack("<BBHI", VERSION, KDF_PBKDF2_SHA256, 0, iterations) + salt + nonce
+    return header + chacha(derive_key(passphrase, salt, iterations)).encrypt(nonce, plain, header)
+
+
+def open_sealed(data, passphrase):
+    if len(data) < HEADER_LEN + 16 or not data.startswith(MAGIC):
+        sys.exit("Not an encrypted flashcards file")
+    version, kdf, _, iterations = struct.unpack("<BBHI", data[4:12])
+    if version != VERSION or kdf != KDF_PBKDF2_SHA256 or not 0 < iterations <= MAX_ITERATIONS:
+        sys.exit("Unsupported encrypted file (version %d, kdf %d)" % (version, kdf))
+    header, sealed = data[:HEADER_LEN], data[HEADER_LEN:]
+    salt, nonce = header[12:12 + SALT_LEN], header[12 + SALT_LEN:]
+    from cryptography.exceptions import InvalidTag
+    try:
+        return chacha(derive_key(passphrase, salt, iterations)).decrypt(nonce, sealed, header)
+    except InvalidTag:
+        sys.exit("Wrong passphrase or damaged file")
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Encrypt or decrypt Precursor flashcard exports.")
+    parser.add_argument("mode", choices=["encrypt", "decrypt"])
+    parser.add_argument("file")
+    parser.add_argument("-o", "--out", help="output file, or - for stdout")
+    parser.add_argument("--iterations", type=int, default=DEFAULT_ITERATIONS,
+                        help="PBKDF2 rounds when encrypting (the device takes longer with more)")
+    args = parser.parse_args()
+
+    with open(args.file, "rb") as f:
+        data = f.read()
+    if args.mode == "encrypt":
+        if not 0 < args.iterations <= MAX_ITERATIONS:
+            sys.exit("--iterations must be between 1 and %d" % MAX_ITERATIONS)
+        passphrase = getpass.getpass("Passphrase: ")
+        if len(passphrase) < 8:
+            sys.exit("Use a passphrase of at least 8 characters")
+        if getpass.getpass("Repeat passphrase: ") != passphrase:
+            sys.exit("Passphrases don't match")
+        result = seal(data, passphrase, args.iterations)
+        out = args.out or args.file + ".enc"
+    else:
+        result = open_sealed(data, getpass.getpass("Passphrase: "))
+        out = args.out or (args.file[:-4] if args.file.endswith(".enc") else args.file + ".dec")
+
+    if out == "-":
+        sys.stdout.buffer.write(result)
+    else:
+        with open(out, "wb") as f:
+            f.write(result)
+        print("Wrote %s (%d bytes)" % (out, len(result)), file=sys.stderr)
+
+
+if __name__ == "__main__":
+    main()